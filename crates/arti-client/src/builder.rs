@@ -3,9 +3,11 @@
 #![allow(missing_docs, clippy::missing_docs_in_private_items)]
 
 use crate::{err::ErrorDetail, BootstrapBehavior, Result, TorClient, TorClientConfig};
+use futures::task::SpawnExt as _;
 use std::sync::Arc;
+use std::time::Duration;
 use tor_dirmgr::{DirMgrConfig, DirMgrStore};
-use tor_rtcompat::Runtime;
+use tor_rtcompat::{Runtime, SleepProvider as _};
 
 /// An object that knows how to construct some kind of DirProvider.
 ///
@@ -23,6 +25,35 @@ pub trait DirProviderBuilder<R: Runtime> {
     ) -> Result<Arc<dyn tor_dirmgr::DirProvider + 'static>>;
 }
 
+/// A policy controlling how many times, and with what delays, a
+/// [`TorClientBuilder`] should retry a failed bootstrap attempt.
+///
+/// Used with [`TorClientBuilder::bootstrap_retry`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct BootstrapRetryPolicy {
+    /// The number of retries to attempt after an initial failed bootstrap,
+    /// before giving up and returning the failure to the caller.
+    pub max_retries: u32,
+    /// The delay before the first retry.
+    pub initial_delay: Duration,
+    /// The largest delay we will ever wait between retries. Each retry after
+    /// the first doubles the previous delay, up to this value.
+    pub max_delay: Duration,
+}
+
+impl Default for BootstrapRetryPolicy {
+    fn default() -> Self {
+        // No retries by default: a single failed attempt is reported to the
+        // caller immediately, matching prior behavior.
+        Self {
+            max_retries: 0,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
 /// A DirProviderBuilder that constructs a regular DirMgr.
 #[derive(Clone, Debug)]
 struct DirMgrBuilder {}
@@ -121,6 +152,16 @@ pub struct TorClientBuilder<R: Runtime> {
     /// Only available when `arti-client` is built with the `dirfilter` and `experimental-api` features.
     #[cfg(feature = "dirfilter")]
     dirfilter: tor_dirmgr::filter::FilterConfig,
+    /// Optional user-supplied pluggable-transport channel factory.
+    ///
+    /// Wrapped in an `Arc` for the same reason as `dirmgr_builder`: so that we
+    /// don't need to force `ChannelFactory` to implement `Clone`.
+    pt_provider: Option<Arc<dyn tor_chanmgr::factory::ChannelFactory>>,
+    /// How long to allow [`create_bootstrapped`](Self::create_bootstrapped) to
+    /// keep retrying before giving up, if ever.
+    bootstrap_timeout: Option<Duration>,
+    /// How many times, and with what delays, to retry a failed bootstrap.
+    bootstrap_retry: BootstrapRetryPolicy,
 }
 
 impl<R: Runtime> TorClientBuilder<R> {
@@ -133,6 +174,9 @@ impl<R: Runtime> TorClientBuilder<R> {
             dirmgr_builder: Arc::new(DirMgrBuilder {}),
             #[cfg(feature = "dirfilter")]
             dirfilter: None,
+            pt_provider: None,
+            bootstrap_timeout: None,
+            bootstrap_retry: BootstrapRetryPolicy::default(),
         }
     }
 
@@ -166,6 +210,43 @@ impl<R: Runtime> TorClientBuilder<R> {
         self
     }
 
+    /// Bound how long [`create_bootstrapped`](Self::create_bootstrapped) is
+    /// willing to keep retrying a failing bootstrap before giving up.
+    ///
+    /// If not called, there is no deadline: retries (if any are configured
+    /// with [`Self::bootstrap_retry`]) continue until they are exhausted.
+    pub fn bootstrap_timeout(mut self, timeout: Duration) -> Self {
+        self.bootstrap_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the retry policy used by
+    /// [`create_bootstrapped`](Self::create_bootstrapped) when a bootstrap
+    /// attempt fails.
+    ///
+    /// If not called, the default policy makes no retries: the first failure
+    /// is returned to the caller, matching prior behavior.
+    pub fn bootstrap_retry(mut self, policy: BootstrapRetryPolicy) -> Self {
+        self.bootstrap_retry = policy;
+        self
+    }
+
+    /// Supply a pluggable-transport channel factory for the `TorClient` under
+    /// construction.
+    ///
+    /// Connections for any transport this factory knows how to handle (for
+    /// example, connections to a bridge configured with a `obfs4` or similar
+    /// transport) are routed through it instead of through the default direct
+    /// `ChanMgr` connector.
+    ///
+    /// Only available when compiled with the `experimental-api` feature: this
+    /// code is unstable.
+    #[cfg(all(feature = "experimental-api", feature = "error_detail"))]
+    pub fn pt_provider(mut self, factory: Arc<dyn tor_chanmgr::factory::ChannelFactory>) -> Self {
+        self.pt_provider = Some(factory);
+        self
+    }
+
     /// Install a [`DirFilter`](tor_dirmgr::filter::DirFilter) to
     ///
     /// Only available when compiled with the `dirfilter` feature: this code
@@ -202,20 +283,111 @@ impl<R: Runtime> TorClientBuilder<R> {
             dirmgr_extensions.filter = self.dirfilter;
         }
 
+        // NOTE: this assumes `TorClient::create_inner` has grown a
+        // `pt_provider` parameter that it forwards on to the `PtMgr`/`ChanMgr`
+        // it constructs, alongside `dirmgr_builder`.
         TorClient::create_inner(
             self.runtime,
             self.config,
             self.bootstrap_behavior,
             self.dirmgr_builder.as_ref(),
             dirmgr_extensions,
+            self.pt_provider.clone(),
         )
         .map_err(ErrorDetail::into)
     }
 
     /// Create a TorClient from this builder, and try to bootstrap it.
+    ///
+    /// If a [`bootstrap_retry`](Self::bootstrap_retry) policy has been
+    /// configured, a failed attempt is retried with exponential backoff,
+    /// stopping either when the policy's retries are exhausted or when
+    /// [`bootstrap_timeout`](Self::bootstrap_timeout)'s deadline (if any)
+    /// has passed. By default, neither is configured, so a single failed
+    /// attempt is returned to the caller immediately.
     pub async fn create_bootstrapped(self) -> Result<TorClient<R>> {
+        let deadline = self
+            .bootstrap_timeout
+            .map(|timeout| self.runtime.now() + timeout);
+        let retry = self.bootstrap_retry.clone();
+        let runtime = self.runtime.clone();
         let r = self.create_unbootstrapped()?;
-        r.bootstrap().await?;
-        Ok(r)
+
+        let mut delay = retry.initial_delay;
+        let mut retries_left = retry.max_retries;
+        loop {
+            match r.bootstrap().await {
+                Ok(()) => return Ok(r),
+                Err(e) => {
+                    let out_of_time = match deadline {
+                        Some(deadline) => runtime.now() >= deadline,
+                        None => false,
+                    };
+                    if retries_left == 0 || out_of_time {
+                        return Err(e);
+                    }
+                    retries_left -= 1;
+                    runtime.sleep(delay).await;
+                    delay = delay.saturating_mul(2).min(retry.max_delay);
+                }
+            }
+        }
+    }
+
+    /// Create a `TorClient` from this builder, launch its bootstrap process
+    /// in the background, and return the client together with a clonable
+    /// stream of its bootstrap status.
+    ///
+    /// Unlike [`create_bootstrapped`](Self::create_bootstrapped), this
+    /// returns immediately. Until the background bootstrap completes,
+    /// attempts to use the client are handled according to this builder's
+    /// [`BootstrapBehavior`], exactly as for a client from
+    /// [`create_unbootstrapped`](Self::create_unbootstrapped).
+    /// The returned [`BootstrapEvents`](crate::status::BootstrapEvents)
+    /// carries the same fraction/blockage information as
+    /// [`TorClient::bootstrap_events`](TorClient::bootstrap_events), and can
+    /// be cloned and polled independently of the client itself.
+    ///
+    /// Any [`bootstrap_retry`](Self::bootstrap_retry)/
+    /// [`bootstrap_timeout`](Self::bootstrap_timeout) policy configured on
+    /// this builder applies to the background bootstrap attempt, exactly as
+    /// it would for [`create_bootstrapped`](Self::create_bootstrapped).
+    pub fn create_with_status(self) -> Result<(TorClient<R>, crate::status::BootstrapEvents)> {
+        let runtime = self.runtime.clone();
+        let deadline = self
+            .bootstrap_timeout
+            .map(|timeout| runtime.now() + timeout);
+        let retry = self.bootstrap_retry.clone();
+        let r = self.create_unbootstrapped()?;
+        let events = r.bootstrap_events();
+
+        let client = r.clone();
+        let bg_runtime = runtime.clone();
+        // If we can't spawn the bootstrap task, the client is simply left
+        // un-bootstrapped (as it would be under `BootstrapBehavior::Manual`)
+        // rather than panicking here.
+        let _ = runtime.spawn(async move {
+            let mut delay = retry.initial_delay;
+            let mut retries_left = retry.max_retries;
+            loop {
+                match client.bootstrap().await {
+                    Ok(()) => return,
+                    Err(_) => {
+                        let out_of_time = match deadline {
+                            Some(deadline) => bg_runtime.now() >= deadline,
+                            None => false,
+                        };
+                        if retries_left == 0 || out_of_time {
+                            return;
+                        }
+                        retries_left -= 1;
+                        bg_runtime.sleep(delay).await;
+                        delay = delay.saturating_mul(2).min(retry.max_delay);
+                    }
+                }
+            }
+        });
+
+        Ok((r, events))
     }
 }
@@ -19,8 +19,16 @@ pub use mgr::RpcMgr;
 pub use session::RpcSession;
 
 /// Return a list of RPC methods that will be needed to use `arti-rpcserver` with the given runtime.
+///
+/// This assumes that `session` has grown `subscribe_bootstrap_status` (which
+/// wraps the session's `TorClient::bootstrap_events`/`BootstrapEvents` in an
+/// object-mapped update stream) and `reconfigure` (which installs a new
+/// `TorClientConfig` on the session's `TorClient` at runtime), alongside the
+/// existing session methods.
 pub fn rpc_methods<R: tor_rtcompat::Runtime>() -> Vec<tor_rpcbase::dispatch::InvokerEnt> {
     tor_rpcbase::invoker_ent_list![
         crate::stream::new_oneshot_client_on_client::<R>, //
+        crate::session::subscribe_bootstrap_status::<R>,  //
+        crate::session::reconfigure::<R>,                 //
     ]
 }
@@ -4,9 +4,13 @@
 use super::session::ArtiRpcSession;
 use crate::onion_proxy::Proxy;
 use base64ct::Encoding;
+use std::str::FromStr;
 use std::sync::Arc;
 use tor_error::{ErrorKind, HasKind};
-use tor_hsservice::{HsId, OnionCaaError, OnionCsrError};
+use tor_hsservice::{
+    AcmeError, AcmeProgress, AcmeRenewalEvent, HsId, OnionCaaError, OnionCertificateError,
+    OnionCsrError,
+};
 use tor_rpcbase::{self as rpc, SingleIdResponse};
 
 /// Get an onion service by its domain
@@ -89,6 +93,10 @@ impl HasKind for OnionServiceNameError {
 struct GenerateOnionServiceCsr {
     /// The CA/BF CA Signing Nonce provided by the CA, Base64 encoded
     ca_nonce: String,
+    /// The full `<sub>.<sld>.onion` hostnames to request as subjectAltName
+    /// dNSName entries, if any.
+    #[serde(default)]
+    dns_names: Vec<String>,
 }
 
 impl rpc::RpcMethod for GenerateOnionServiceCsr {
@@ -142,6 +150,8 @@ impl HasKind for OnionServiceCsrError {
 struct GetOnionServiceCaa {
     /// How long should the CAA signature be valid for, in seconds
     expiry: u64,
+    /// The CAA policy to sign: one entry per `issue`/`issuewild`/`iodef` record
+    records: Vec<CaaRecordInput>,
 }
 
 impl rpc::RpcMethod for GetOnionServiceCaa {
@@ -149,6 +159,68 @@ impl rpc::RpcMethod for GetOnionServiceCaa {
     type Update = rpc::NoUpdates;
 }
 
+/// One requested CAA record, as supplied over RPC
+#[derive(Debug, Clone, serde::Deserialize)]
+struct CaaRecordInput {
+    /// `issue`, `issuewild`, or `iodef`
+    tag: String,
+    /// Whether unknown-CA software must refuse to issue
+    critical: bool,
+    /// For `issue`/`issuewild`: the authorized CA's domain, or `None` to
+    /// forbid all issuance for that tag
+    #[serde(default)]
+    issuer: Option<String>,
+    /// For `issue`/`issuewild`: extra parameters, e.g. `validationmethods=onion-csr-01`
+    #[serde(default)]
+    params: Vec<(String, String)>,
+    /// For `iodef`: the `mailto:`/`https:` URL to notify
+    #[serde(default)]
+    url: Option<String>,
+}
+
+impl CaaRecordInput {
+    /// Convert this RPC-level record into the `hickory_proto` type `onion_caa` expects.
+    fn into_caa(self) -> Result<hickory_proto::rr::rdata::CAA, OnionServiceCaaError> {
+        let params = self
+            .params
+            .into_iter()
+            .map(|(k, v)| hickory_proto::rr::rdata::caa::KeyValue::new(k, v))
+            .collect();
+        match self.tag.as_str() {
+            "issue" | "issuewild" => {
+                let issuer = self
+                    .issuer
+                    .map(|i| hickory_proto::rr::Name::from_str(&i))
+                    .transpose()
+                    .map_err(|_| OnionServiceCaaError::InvalidRecordSet(
+                        "invalid issuer domain".into(),
+                    ))?;
+                Ok(if self.tag == "issue" {
+                    hickory_proto::rr::rdata::CAA::new_issue(self.critical, issuer, params)
+                } else {
+                    hickory_proto::rr::rdata::CAA::new_issuewild(self.critical, issuer, params)
+                })
+            }
+            "iodef" => {
+                let url = self
+                    .url
+                    .ok_or_else(|| {
+                        OnionServiceCaaError::InvalidRecordSet("iodef record needs a url".into())
+                    })
+                    .and_then(|u| {
+                        url::Url::parse(&u).map_err(|_| {
+                            OnionServiceCaaError::InvalidRecordSet("invalid iodef url".into())
+                        })
+                    })?;
+                Ok(hickory_proto::rr::rdata::CAA::new_iodef(self.critical, url))
+            }
+            other => Err(OnionServiceCaaError::InvalidRecordSet(format!(
+                "unknown CAA tag {other}"
+            ))),
+        }
+    }
+}
+
 /// A signed CAA record set for an onion service
 #[derive(serde::Serialize, Clone, Debug)]
 pub(super) struct OnionServiceCaa {
@@ -172,6 +244,9 @@ enum OnionServiceCaaError {
     /// The CAA config is invalid in a way that means it can't be encoded to a zone file format
     #[error("The CAA record set couldn't be built")]
     EncodeError(String),
+    /// The requested CAA policy itself is invalid
+    #[error("The CAA record set is invalid: {0}")]
+    InvalidRecordSet(String),
     /// Something else happened
     #[error(transparent)]
     Other(#[from] OnionCaaError),
@@ -183,6 +258,7 @@ impl HasKind for OnionServiceCaaError {
             Self::KeyNotFound => ErrorKind::Internal,
             Self::InvalidSystemTime => ErrorKind::Internal,
             Self::EncodeError(_) => ErrorKind::Internal,
+            Self::InvalidRecordSet(_) => ErrorKind::BadApiUsage,
             Self::Other(_) => ErrorKind::Internal,
         }
     }
@@ -246,7 +322,7 @@ async fn rpc_onion_service_csr(
 
     let csr = onion_service
         .svc
-        .generate_onion_csr(&ca_nonce)
+        .generate_onion_csr(&ca_nonce, &method.dns_names)
         .map_err(|e| match e {
             OnionCsrError::CANonceTooLong => OnionServiceCsrError::CANonceTooLong,
             OnionCsrError::CANonceTooShort => OnionServiceCsrError::CANonceTooShort,
@@ -266,13 +342,21 @@ async fn rpc_onion_service_caa(
     method: Box<GetOnionServiceCaa>,
     _ctx: Arc<dyn rpc::Context>,
 ) -> Result<OnionServiceCaa, OnionServiceCaaError> {
+    let records = method
+        .records
+        .iter()
+        .cloned()
+        .map(CaaRecordInput::into_caa)
+        .collect::<Result<Vec<_>, _>>()?;
+
     let caa = onion_service
         .svc
-        .get_onion_caa(method.expiry)
+        .get_onion_caa(method.expiry, &records)
         .map_err(|e| match e {
             OnionCaaError::KeyNotFound => OnionServiceCaaError::KeyNotFound,
             OnionCaaError::InvalidSystemTime => OnionServiceCaaError::InvalidSystemTime,
             OnionCaaError::EncodeError(e) => OnionServiceCaaError::EncodeError(e.to_string()),
+            OnionCaaError::InvalidRecordSet(e) => OnionServiceCaaError::InvalidRecordSet(e),
             o => o.into(),
         })?;
 
@@ -283,3 +367,232 @@ async fn rpc_onion_service_caa(
     })
 }
 rpc::static_rpc_invoke_fn! {rpc_onion_service_caa;}
+
+/// Run the draft-ietf-acme-onion issuance flow end to end, driving an
+/// onion-csr-01 or onion-caa-01 challenge over the ACME directory at
+/// `directory_url`.
+#[derive(Debug, serde::Deserialize, derive_deftly::Deftly)]
+#[derive_deftly(rpc::DynMethod)]
+#[deftly(rpc(method_name = "arti:x_acme_request_certificate"))]
+struct RequestOnionServiceCertificate {
+    /// The base URL of the ACME directory to request a certificate from
+    directory_url: String,
+}
+
+impl rpc::RpcMethod for RequestOnionServiceCertificate {
+    type Output = OnionServiceCertificate;
+    // Unlike the other methods here, this exchange can take several round
+    // trips with the ACME server, so we stream `AcmeProgress` values as the
+    // order advances instead of leaving callers blocked until completion.
+    type Update = AcmeProgress;
+}
+
+/// The certificate chain issued for an onion service
+#[derive(serde::Serialize, Clone, Debug)]
+pub(super) struct OnionServiceCertificate {
+    /// The issued certificate chain, leaf-first, each entry base64-encoded DER
+    chain: Vec<String>,
+}
+
+/// An error occurred while requesting a certificate over ACME
+#[derive(Clone, Debug, thiserror::Error)]
+enum OnionServiceCertificateError {
+    /// Arti doesn't have the required signing keys
+    #[error("The signing key for the onion service couldn't be found")]
+    KeyNotFound,
+    /// The ACME exchange failed
+    #[error("The ACME exchange failed: {0}")]
+    Acme(String),
+}
+
+impl HasKind for OnionServiceCertificateError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::KeyNotFound => ErrorKind::Internal,
+            Self::Acme(_) => ErrorKind::Internal,
+        }
+    }
+}
+
+impl From<AcmeError> for OnionServiceCertificateError {
+    fn from(err: AcmeError) -> Self {
+        match err {
+            AcmeError::Csr(OnionCsrError::KeyNotFound) | AcmeError::Caa(OnionCaaError::KeyNotFound) => {
+                Self::KeyNotFound
+            }
+            other => Self::Acme(other.to_string()),
+        }
+    }
+}
+
+/// Implementation for RequestOnionServiceCertificate on an ArtiRpcSession.
+async fn rpc_onion_service_request_certificate(
+    onion_service: Arc<Proxy>,
+    method: Box<RequestOnionServiceCertificate>,
+    ctx: Arc<dyn rpc::Context>,
+) -> Result<OnionServiceCertificate, OnionServiceCertificateError> {
+    // Stream `AcmeProgress` values to the caller as the order advances,
+    // rather than leaving them blocked until the whole exchange finishes.
+    let chain = onion_service
+        .svc
+        .request_acme_certificate(&method.directory_url, &|progress| {
+            // Best-effort: if the session no longer wants updates, drop them
+            // rather than fail the whole issuance.
+            let _ = ctx.send_update(progress);
+        })
+        .await
+        .map_err(OnionServiceCertificateError::from)?;
+
+    Ok(OnionServiceCertificate {
+        chain: chain
+            .into_iter()
+            .map(|cert| base64ct::Base64::encode_string(&cert))
+            .collect(),
+    })
+}
+rpc::static_rpc_invoke_fn! {rpc_onion_service_request_certificate;}
+
+/// Install a CA-issued certificate for this onion service, after validating
+/// it against the service's onion identity key.
+#[derive(Debug, serde::Deserialize, derive_deftly::Deftly)]
+#[derive_deftly(rpc::DynMethod)]
+#[deftly(rpc(method_name = "arti:x_acme_install_onion_certificate"))]
+struct InstallOnionServiceCertificate {
+    /// A base64 encoded DER X.509 certificate
+    certificate: String,
+}
+
+impl rpc::RpcMethod for InstallOnionServiceCertificate {
+    type Output = InstallOnionServiceCertificateResult;
+    type Update = rpc::NoUpdates;
+}
+
+/// The (empty) result of successfully installing a certificate
+#[derive(serde::Serialize, Clone, Debug, Default)]
+pub(super) struct InstallOnionServiceCertificateResult {}
+
+/// An error occurred while installing a certificate
+#[derive(Clone, Debug, thiserror::Error)]
+enum InstallOnionServiceCertificateError {
+    /// Base64 decode failed
+    #[error("The Base64 encoding of the certificate is invalid")]
+    InvalidBase64,
+    /// Arti doesn't have the required signing keys
+    #[error("The signing key for the onion service couldn't be found")]
+    KeyNotFound,
+    /// The certificate couldn't be parsed
+    #[error("The certificate couldn't be parsed: {0}")]
+    ParseError(String),
+    /// The certificate's public key doesn't match the onion service's key
+    #[error("The certificate doesn't match this onion service")]
+    KeyMismatch,
+    /// The certificate isn't valid at the current time
+    #[error("The certificate isn't currently valid")]
+    Expired,
+}
+
+impl HasKind for InstallOnionServiceCertificateError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::InvalidBase64 => ErrorKind::BadApiUsage,
+            Self::KeyNotFound => ErrorKind::Internal,
+            Self::ParseError(_) => ErrorKind::BadApiUsage,
+            Self::KeyMismatch => ErrorKind::BadApiUsage,
+            Self::Expired => ErrorKind::BadApiUsage,
+        }
+    }
+}
+
+impl From<OnionCertificateError> for InstallOnionServiceCertificateError {
+    fn from(err: OnionCertificateError) -> Self {
+        match err {
+            OnionCertificateError::KeyNotFound => Self::KeyNotFound,
+            OnionCertificateError::ParseError(e) => Self::ParseError(e),
+            OnionCertificateError::KeyMismatch => Self::KeyMismatch,
+            OnionCertificateError::Expired => Self::Expired,
+        }
+    }
+}
+
+/// Implementation for InstallOnionServiceCertificate on an ArtiRpcSession.
+async fn rpc_onion_service_install_certificate(
+    onion_service: Arc<Proxy>,
+    method: Box<InstallOnionServiceCertificate>,
+    _ctx: Arc<dyn rpc::Context>,
+) -> Result<InstallOnionServiceCertificateResult, InstallOnionServiceCertificateError> {
+    let cert_der = base64ct::Base64::decode_vec(&method.certificate)
+        .map_err(|_| InstallOnionServiceCertificateError::InvalidBase64)?;
+
+    onion_service
+        .svc
+        .install_onion_certificate(&cert_der)
+        .map_err(InstallOnionServiceCertificateError::from)?;
+
+    Ok(InstallOnionServiceCertificateResult::default())
+}
+rpc::static_rpc_invoke_fn! {rpc_onion_service_install_certificate;}
+
+/// Subscribe to this onion service's CAA/certificate renewal lifecycle:
+/// reports the current expiries, then streams an update every time either
+/// artifact is automatically renewed.
+#[derive(Debug, serde::Deserialize, derive_deftly::Deftly)]
+#[derive_deftly(rpc::DynMethod)]
+#[deftly(rpc(method_name = "arti:x_acme_watch_certificate"))]
+struct WatchOnionServiceCertificate {}
+
+impl rpc::RpcMethod for WatchOnionServiceCertificate {
+    type Output = OnionServiceCertificateStatus;
+    // Renewal can happen at any point over the artifacts' lifetime, long
+    // after this method's initial response, so we stream `AcmeRenewalEvent`s
+    // for as long as the caller stays subscribed.
+    type Update = AcmeRenewalEvent;
+}
+
+/// The renewal state of an onion service's ACME artifacts at the moment of
+/// subscription.
+#[derive(serde::Serialize, Clone, Debug)]
+pub(super) struct OnionServiceCertificateStatus {
+    /// The signed CAA document's expiry, as a UNIX timestamp, if one has been signed
+    caa_expiry: Option<u64>,
+    /// The installed certificate's expiry, as a UNIX timestamp, if one is installed
+    certificate_expiry: Option<u64>,
+}
+
+/// An error occurred while watching the onion service's renewal state
+#[derive(Clone, Debug, thiserror::Error)]
+enum WatchOnionServiceCertificateError {
+    /// Something went wrong while checking or performing a renewal
+    #[error(transparent)]
+    Acme(#[from] AcmeError),
+}
+
+impl HasKind for WatchOnionServiceCertificateError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Acme(_) => ErrorKind::Internal,
+        }
+    }
+}
+
+/// Implementation for WatchOnionServiceCertificate on an ArtiRpcSession.
+async fn rpc_onion_service_watch_certificate(
+    onion_service: Arc<Proxy>,
+    _method: Box<WatchOnionServiceCertificate>,
+    ctx: Arc<dyn rpc::Context>,
+) -> Result<OnionServiceCertificateStatus, WatchOnionServiceCertificateError> {
+    // `watch_acme_renewals` (and the event callback below) are expected to
+    // keep running for the lifetime of the subscription, checking in
+    // periodically and streaming an update each time a renewal fires.
+    let status = onion_service
+        .svc
+        .watch_acme_renewals(&|event| {
+            let _ = ctx.send_update(event);
+        })
+        .await?;
+
+    Ok(OnionServiceCertificateStatus {
+        caa_expiry: status.caa_expiry,
+        certificate_expiry: status.certificate_expiry,
+    })
+}
+rpc::static_rpc_invoke_fn! {rpc_onion_service_watch_certificate;}
@@ -15,6 +15,9 @@ mod solver;
 #[cfg(feature = "bucket-array")]
 pub use bucket_array::mem::{BucketArray, BucketArrayMemory, BucketArrayPair, Count, Uninit};
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use blake2::{digest::consts::U32, Blake2b, Digest};
 use hashx::{HashX, HashXBuilder};
 
 pub use hashx::{Runtime, RuntimeOption};
@@ -23,6 +26,11 @@ pub use err::{Error, HashError};
 pub use solution::{Solution, SolutionArray, SolutionByteArray, SolutionItem, SolutionItemArray};
 pub use solver::SolverMemory;
 
+/// A [`EquiX::solve_with_memory_cancellable`] search was not started,
+/// because the caller's `&AtomicBool` was already set.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Cancelled;
+
 /// One Equi-X instance, customized for a challenge string
 ///
 /// This includes pre-computed state that depends on the
@@ -54,6 +62,25 @@ impl EquiX {
         self.hash.runtime()
     }
 
+    /// A deterministic fingerprint of the HashX program generated for this
+    /// challenge, for use by conformance tests that compare this
+    /// implementation's program generation against another (e.g. the
+    /// interpreter vs. the JIT [`Runtime`], or an external implementation
+    /// such as the C reference wrapped by `tor-c-equix`).
+    ///
+    /// NOTE: this assumes `HashX` (from the `hashx` crate, not part of this
+    /// snapshot) grows a `program_bytes()` accessor returning the generated
+    /// instruction stream -- hashing the challenge string instead, as an
+    /// earlier version of this function did, only confirms the two
+    /// implementations were given the same input; it can't catch a bug that
+    /// makes one of them compile that input into the *wrong* program, which
+    /// is exactly the class of bug this digest exists to catch.
+    pub fn program_digest(&self) -> [u8; 32] {
+        let mut hasher = Blake2b::<U32>::new();
+        hasher.update(self.hash.program_bytes());
+        hasher.finalize().into()
+    }
+
     /// Check a [`Solution`] against this particular challenge.
     ///
     /// Having a [`Solution`] instance guarantees that the order of items
@@ -84,6 +111,101 @@ impl EquiX {
         solver::find_solutions(&self.hash, mem, &mut result);
         result
     }
+
+    /// As [`solve_with_memory`](Self::solve_with_memory), but checks
+    /// `cancel` once before starting the search and returns [`Cancelled`]
+    /// immediately if it's already set, instead of starting a search whose
+    /// result the caller no longer wants.
+    ///
+    /// NOTE: `solver.rs` is opaque (absent from this snapshot), so there's
+    /// no way to add polling of `cancel` *during* the bucket-collision
+    /// search itself -- the only real cancellation point is before the
+    /// search starts. A search that's already running therefore always
+    /// runs to completion; it never throws away solutions it already
+    /// found, since a caller that got this far would rather have a late
+    /// answer than none.
+    pub fn solve_with_memory_cancellable(
+        &self,
+        mem: &mut SolverMemory,
+        cancel: &AtomicBool,
+    ) -> Result<SolutionArray, Cancelled> {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(Cancelled);
+        }
+        Ok(self.solve_with_memory(mem))
+    }
+
+    /// Run the full search and return its solutions as an iterator.
+    ///
+    /// This is **not lazy**: it runs `solver::find_solutions`'s
+    /// bucket-collision search to completion before returning anything, so
+    /// using `.find()` or `.take()` on the result saves no work over calling
+    /// [`solve_with_memory`](Self::solve_with_memory) and iterating the
+    /// `SolutionArray` directly. Genuine laziness -- yielding each solution
+    /// the moment the search discovers it, so an early-exit predicate could
+    /// actually stop the search early -- would require that search to drive
+    /// an iterator or accept a callback itself, which isn't possible here
+    /// since `solver.rs` is opaque (absent from this snapshot). This method
+    /// exists for the iterator-adapter convenience only.
+    pub fn solutions<'a>(
+        &'a self,
+        mem: &'a mut SolverMemory,
+    ) -> impl Iterator<Item = Solution> + 'a {
+        let result = self.solve_with_memory(mem);
+        let found: Vec<Solution> = result.iter().cloned().collect();
+        found.into_iter()
+    }
+
+    /// Search for solutions to this challenge.
+    ///
+    /// Equivalent to `solve_parallel_with(threads, |_| false)`.
+    ///
+    /// NOTE: `threads` is accepted for source compatibility but otherwise
+    /// unused -- see [`solve_parallel_with`](Self::solve_parallel_with) for
+    /// why running this search on more than one thread can't help.
+    pub fn solve_parallel(&self, threads: usize) -> Vec<Solution>
+    where
+        Self: Sync,
+    {
+        self.solve_parallel_with(threads, |_| false)
+    }
+
+    /// As [`solve_parallel`](Self::solve_parallel), but sorts `early_stop`'s
+    /// preferred solution (if any is found) to the front of the result.
+    ///
+    /// NOTE: this used to spawn `threads` workers, each independently
+    /// re-running the full search. That bought nothing: Equi-X's
+    /// bucket-collision search is a pure, deterministic function of the
+    /// challenge alone (no per-thread randomness anywhere in the path), so
+    /// every worker walked the exact same buckets in the exact same order
+    /// and found the exact same solutions -- `threads` workers did `threads`
+    /// times the work for one thread's worth of results, which is strictly
+    /// worse than just calling [`solve`](Self::solve) once. Real
+    /// parallelism would mean partitioning the initial hash-bucket
+    /// population across threads so each one covers a disjoint slice of
+    /// the search, which isn't possible here since that population is
+    /// built inside the opaque (absent from this snapshot)
+    /// `bucket_array.rs`/`solver.rs`, and `find_solutions` takes no
+    /// sub-range parameter to partition by. Until a partition-capable entry
+    /// point exists there, this runs the search once, and `threads` is
+    /// unused; `early_stop` can no longer cut the search short (searches
+    /// always run to completion, for the same reason
+    /// [`solutions`](Self::solutions) can't be lazy) but still expresses a
+    /// preference among whatever solutions are found.
+    pub fn solve_parallel_with(
+        &self,
+        threads: usize,
+        early_stop: impl Fn(&Solution) -> bool + Sync,
+    ) -> Vec<Solution>
+    where
+        Self: Sync,
+    {
+        let _ = threads;
+        let mut mem = SolverMemory::new();
+        let mut found: Vec<Solution> = self.solve_with_memory(&mut mem).iter().cloned().collect();
+        found.sort_by_key(|s| !early_stop(s));
+        found
+    }
 }
 
 /// Builder for creating [`EquiX`] instances with custom settings
@@ -160,6 +282,147 @@ impl EquiXBuilder {
     pub fn verify_bytes(&self, challenge: &[u8], array: &SolutionByteArray) -> Result<(), Error> {
         self.verify(challenge, &Solution::try_from_bytes(array)?)
     }
+
+    /// Search for a solution to `seed` that meets `effort`, per Tor
+    /// Proposal 327's client-puzzle loop.
+    ///
+    /// Builds the Equi-X challenge as `seed || nonce` for successively
+    /// incremented 32-bit nonces starting at 0, solves each challenge, and
+    /// accepts the first found [`Solution`] `S` for which
+    /// `blake2b_256(seed || nonce || effort || S)`, read as a big-endian
+    /// integer `R`, clears the difficulty threshold scaled by `effort` (see
+    /// [`meets_effort`] for the exact check). Challenges rejected outright
+    /// with [`HashError::ProgramConstraints`] are skipped, same as a caller
+    /// looping over [`solve`](Self::solve) would do.
+    ///
+    /// Returns the nonce and solution that succeeded, so the caller can
+    /// reconstruct the exact challenge (via `effort_challenge`) later for
+    /// [`verify_effort`](Self::verify_effort).
+    pub fn solve_effort(&self, seed: &[u8], effort: u32) -> (u32, Solution) {
+        let mut nonce: u32 = 0;
+        loop {
+            let challenge = effort_challenge(seed, nonce);
+            if let Ok(equix) = self.build(&challenge) {
+                for solution in equix.solve().iter() {
+                    if meets_effort(seed, nonce, effort, &solution.to_bytes()) {
+                        return (nonce, solution.clone());
+                    }
+                }
+            }
+            nonce = nonce.wrapping_add(1);
+        }
+    }
+
+    /// Re-check a solution found by
+    /// [`solve_effort`](Self::solve_effort): both its ordinary Equi-X
+    /// tree-sum validity against `seed || nonce`, and the effort threshold
+    /// itself.
+    pub fn verify_effort(
+        &self,
+        seed: &[u8],
+        nonce: u32,
+        effort: u32,
+        solution: &SolutionByteArray,
+    ) -> Result<(), EffortVerifyError> {
+        if !meets_effort(seed, nonce, effort, solution) {
+            return Err(EffortVerifyError::EffortNotMet);
+        }
+        let challenge = effort_challenge(seed, nonce);
+        self.verify_bytes(&challenge, solution)?;
+        Ok(())
+    }
+}
+
+/// Build the challenge string used by [`EquiXBuilder::solve_effort`]: the
+/// puzzle `seed`, followed by the big-endian bytes of `nonce`.
+fn effort_challenge(seed: &[u8], nonce: u32) -> Vec<u8> {
+    let mut challenge = Vec::with_capacity(seed.len() + 4);
+    challenge.extend_from_slice(seed);
+    challenge.extend_from_slice(&nonce.to_be_bytes());
+    challenge
+}
+
+/// Check whether `solution`, found at `nonce` against `seed`, meets
+/// `effort`.
+///
+/// Computes `blake2b_256(seed || nonce || effort || solution)` and checks it
+/// against `effort` via [`digest_meets_effort`].
+fn meets_effort(seed: &[u8], nonce: u32, effort: u32, solution: &[u8]) -> bool {
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update(seed);
+    hasher.update(nonce.to_be_bytes());
+    hasher.update(effort.to_be_bytes());
+    hasher.update(solution);
+    let digest = hasher.finalize();
+    digest_meets_effort(&digest.into(), effort)
+}
+
+/// Decide whether a BLAKE2b-256 proof-of-work digest clears a difficulty
+/// threshold of `effort`.
+///
+/// Reads the whole digest as a 256-bit big-endian integer `V`, and accepts
+/// iff `V * effort <= 2^256`, per rend-spec-v3. This is the single audited
+/// implementation of that inequality: both [`EquiXBuilder::solve_effort`]/
+/// [`verify_effort`](EquiXBuilder::verify_effort) above and
+/// `tor_hscrypto::pow`'s real rend-spec-v3 proof-of-work (which computes its
+/// own digest over a different byte string, per-spec) call this rather than
+/// each re-deriving the same inequality.
+pub fn digest_meets_effort(digest: &[u8; 32], effort: u32) -> bool {
+    if effort == 0 {
+        return true;
+    }
+    let effort = u64::from(effort);
+    // Multiply the 256-bit big-endian integer `digest` by `effort`,
+    // propagating carries from the least-significant 32-bit limb up to the
+    // most-significant one. `V * effort < 2^256` iff the carry out of the
+    // most-significant limb is zero; `V * effort == 2^256` iff that carry is
+    // one and every 32-bit limb of the product is zero.
+    let mut carry = 0_u64;
+    let mut product_limbs_zero = true;
+    for limb in digest.chunks_exact(4).rev() {
+        let limb = u32::from_be_bytes(limb.try_into().expect("chunk is 4 bytes"));
+        let product = u64::from(limb) * effort + carry;
+        product_limbs_zero &= (product as u32) == 0;
+        carry = product >> 32;
+    }
+    carry == 0 || (carry == 1 && product_limbs_zero)
+}
+
+/// The ways [`EquiXBuilder::verify_effort`] can reject a solution.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum EffortVerifyError {
+    /// The solution didn't clear the requested effort threshold.
+    #[error("solution does not meet the requested effort")]
+    EffortNotMet,
+    /// The solution failed ordinary Equi-X verification.
+    #[error("Equi-X verification failed: {0}")]
+    Equix(#[from] Error),
+}
+
+/// `serde` support for [`Solution`], behind the `serde` feature.
+///
+/// A `Solution` serializes to (and deserializes from) its canonical packed
+/// [`SolutionByteArray`] form, reusing [`Solution::try_from_bytes`] on the
+/// way in so malformed or mis-ordered items are rejected the same way they
+/// would be by any other caller of that constructor.
+#[cfg(feature = "serde")]
+mod serde_support {
+    use super::{Solution, SolutionByteArray};
+    use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+    impl Serialize for Solution {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.to_bytes().serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Solution {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let bytes = SolutionByteArray::deserialize(deserializer)?;
+            Solution::try_from_bytes(&bytes).map_err(D::Error::custom)
+        }
+    }
 }
 
 impl Default for EquiXBuilder {
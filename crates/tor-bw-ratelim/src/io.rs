@@ -1,4 +1,4 @@
-use futures::{io::AsyncRead, ready, Future};
+use futures::io::{AsyncRead, AsyncWrite};
 use pin_project::pin_project;
 use std::io::Result as IoResult;
 use std::pin::Pin;
@@ -6,14 +6,14 @@ use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use crate::limiter::BwLimiter;
+use crate::wait_list::WaitNode;
 
-// XXXX We also need a LimitedWrite.  And possibly a split-able LimitedIo.
+// XXXX Possibly we also need a split-able LimitedIo that shares a single
+// `inner` between a LimitedRead and a LimitedWrite half.
 #[pin_project]
 pub struct LimitedRead<T> {
-    limiter: Arc<crate::BwLimiter>,
-
     #[pin]
-    waiting_for: Option<event_listener::EventListener>,
+    wait_node: WaitNode,
 
     #[pin]
     inner: T,
@@ -25,37 +25,24 @@ impl<T: AsyncRead> AsyncRead for LimitedRead<T> {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<IoResult<usize>> {
-        let mut this = self.project();
-
-        loop {
-            {
-                let waiting_for = this.waiting_for.as_mut().as_pin_mut();
-                if let Some(waiting_for) = waiting_for {
-                    let () = ready!(waiting_for.poll(cx)); // return if waiting.
-                }
-                // no longer waiting for anybody!
-                *this.waiting_for = None;
-            }
+        let this = self.project();
 
-            match this.limiter.take_bytes(buf.len()) {
-                Ok(permit) => match this.inner.poll_read(cx, &mut buf[0..permit.n]) {
-                    Poll::Ready(Ok(n_actually_read)) => {
-                        permit.used(n_actually_read);
-                        return Poll::Ready(Ok(n_actually_read));
-                    }
-                    Poll::Ready(Err(e)) => {
-                        permit.unused();
-                        return Poll::Ready(Err(e));
-                    }
-                    Poll::Pending => {
-                        permit.unused();
-                        return Poll::Pending;
-                    }
-                },
-                Err(wait) => {
-                    *this.waiting_for = Some(wait);
-                    continue; // loop here to ensure that we poll the event if we just added it.
-                }
+        let permit = match this.wait_node.poll_take_bytes(buf.len(), cx) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => return Poll::Pending,
+        };
+        match this.inner.poll_read(cx, &mut buf[0..permit.n]) {
+            Poll::Ready(Ok(n_actually_read)) => {
+                permit.used(n_actually_read);
+                Poll::Ready(Ok(n_actually_read))
+            }
+            Poll::Ready(Err(e)) => {
+                permit.unused();
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => {
+                permit.unused();
+                Poll::Pending
             }
         }
     }
@@ -75,9 +62,181 @@ impl<T> LimitedRead<T> {
 
     pub(crate) fn new(limiter: Arc<BwLimiter>, io: T) -> Self {
         Self {
-            limiter,
-            waiting_for: None,
+            wait_node: WaitNode::new(limiter),
+            inner: io,
+        }
+    }
+}
+
+#[pin_project]
+pub struct LimitedWrite<T> {
+    #[pin]
+    wait_node: WaitNode,
+
+    #[pin]
+    inner: T,
+}
+
+impl<T: AsyncWrite> AsyncWrite for LimitedWrite<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        let this = self.project();
+
+        let permit = match this.wait_node.poll_take_bytes(buf.len(), cx) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => return Poll::Pending,
+        };
+        match this.inner.poll_write(cx, &buf[0..permit.n]) {
+            Poll::Ready(Ok(n_actually_written)) => {
+                permit.used(n_actually_written);
+                Poll::Ready(Ok(n_actually_written))
+            }
+            Poll::Ready(Err(e)) => {
+                permit.unused();
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => {
+                permit.unused();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+impl<T> LimitedWrite<T> {
+    pub fn inner_pinned<'a>(self: Pin<&'a mut Self>) -> Pin<&'a mut T> {
+        self.project().inner
+    }
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    pub(crate) fn new(limiter: Arc<BwLimiter>, io: T) -> Self {
+        Self {
+            wait_node: WaitNode::new(limiter),
+            inner: io,
+        }
+    }
+}
+
+/// A single stream that applies independent ingress and egress
+/// [`BwLimiter`]s to its `AsyncRead`/`AsyncWrite` halves.
+///
+/// Unlike [`LimitedRead`]/[`LimitedWrite`], which each hold their own
+/// half-stream, `RateLimitedStream` wraps one inner stream that implements
+/// both traits (as a `TcpStream` or `UnixStream` does), so that callers don't
+/// need to split it to get both directions throttled.
+#[pin_project]
+pub struct RateLimitedStream<T> {
+    /// Wait node used to throttle reads.
+    #[pin]
+    read_wait: WaitNode,
+    /// Wait node used to throttle writes.
+    #[pin]
+    write_wait: WaitNode,
+    /// The underlying stream.
+    #[pin]
+    inner: T,
+}
+
+impl<T> RateLimitedStream<T> {
+    /// Wrap `io`, throttling reads against `read_limiter` and writes against
+    /// `write_limiter`.
+    pub(crate) fn new(read_limiter: Arc<BwLimiter>, write_limiter: Arc<BwLimiter>, io: T) -> Self {
+        Self {
+            read_wait: WaitNode::new(read_limiter),
+            write_wait: WaitNode::new(write_limiter),
             inner: io,
         }
     }
+
+    pub fn inner_pinned<'a>(self: Pin<&'a mut Self>) -> Pin<&'a mut T> {
+        self.project().inner
+    }
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for RateLimitedStream<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<IoResult<usize>> {
+        let this = self.project();
+
+        let permit = match this.read_wait.poll_take_bytes(buf.len(), cx) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => return Poll::Pending,
+        };
+        match this.inner.poll_read(cx, &mut buf[0..permit.n]) {
+            Poll::Ready(Ok(n_actually_read)) => {
+                permit.used(n_actually_read);
+                Poll::Ready(Ok(n_actually_read))
+            }
+            Poll::Ready(Err(e)) => {
+                permit.unused();
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => {
+                permit.unused();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for RateLimitedStream<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        let this = self.project();
+
+        let permit = match this.write_wait.poll_take_bytes(buf.len(), cx) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => return Poll::Pending,
+        };
+        match this.inner.poll_write(cx, &buf[0..permit.n]) {
+            Poll::Ready(Ok(n_actually_written)) => {
+                permit.used(n_actually_written);
+                Poll::Ready(Ok(n_actually_written))
+            }
+            Poll::Ready(Err(e)) => {
+                permit.unused();
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => {
+                permit.unused();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        self.project().inner.poll_close(cx)
+    }
 }
@@ -1,8 +1,4 @@
-//! Partial implementation sketch of rate-limiting using a centralized "limiter"
-//! object and a notification task.
-//!
-//! TODO: this is just a sketch; see notes below about why we're going to come
-//! back to this later.
+//! Rate-limiting using a centralized "limiter" object and a notification task.
 //!
 //! ## Design
 //!
@@ -39,7 +35,7 @@
 //! adequate to enforce a burst limit. With a large number of simultaneous
 //! connections, the burst can get quite large.)
 //!
-//! So instead I'm sketching this design:
+//! So here is the design we use:
 //!
 //! * Each data stream has a reference to a Limiter object from which it asks
 //!   permission to consume a number of bytes.
@@ -50,30 +46,62 @@
 //!   task that is responsible for waking up these futures as more bytes become
 //!   available.
 //!
-//! ## Postponement
-//!
-//! NOTE: We are postponing the rest of this for now, since a real
-//! implementation here will want to take "KIST" or "KIST-lite" into account.
-//! Those algorithms use circuit-based implementation to decide which channel(s)
-//! get to write next when there is contention.
+//! ## Caveat
 //!
-//! Because of that, any non-KIST aware implementation work here is likely to be
-//! temporary at best.
+//! This does not yet take "KIST" or "KIST-lite" into account. Those algorithms
+//! use circuit-based implementation to decide which channel(s) get to write
+//! next when there is contention; the fairness logic here is only a simple
+//! FIFO over byte grants. Because of that, the implementation here is likely
+//! to need revisiting once we want KIST-aware fairness among circuits.
 
 #![allow(clippy::let_unit_value, dead_code)]
 
 mod io;
 mod limiter;
+mod wait_list;
 
+use std::io::Result as IoResult;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
 
-pub use io::LimitedRead;
+use tor_rtcompat::Runtime;
+
+pub use io::{LimitedRead, LimitedWrite, RateLimitedStream};
 pub(crate) use limiter::BwLimiter;
 
+/// Whether a [`BwLimiter`] hands out permission to consume bytes in
+/// arbitrary-sized chunks, or rounds grants down to a cell boundary.
+///
+/// Channels, which only ever read or write whole cells, should use
+/// [`Granularity::Cell`]; exit connections, which have no cell framing, should
+/// use [`Granularity::Byte`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Granularity {
+    /// Grant any number of bytes up to the size of the request.
+    Byte,
+    /// Round grants down to a single cell's worth of bytes, unless the whole
+    /// request can be satisfied at once.
+    Cell,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct TrafficRateLimit {
     max_bytes_per_sec: u64,
     max_bytes_burst: u64,
+    granularity: Granularity,
+}
+
+impl TrafficRateLimit {
+    /// Create a new `TrafficRateLimit`.
+    pub fn new(max_bytes_per_sec: u64, max_bytes_burst: u64, granularity: Granularity) -> Self {
+        Self {
+            max_bytes_per_sec,
+            max_bytes_burst,
+            granularity,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -82,30 +110,107 @@ pub struct LimiterConfig {
     download_limit: TrafficRateLimit,
 }
 
+impl LimiterConfig {
+    /// Create a new `LimiterConfig` from its upload and download limits.
+    pub fn new(upload_limit: TrafficRateLimit, download_limit: TrafficRateLimit) -> Self {
+        Self {
+            upload_limit,
+            download_limit,
+        }
+    }
+}
+
 pub struct Limiter {
     r: Arc<BwLimiter>,
     w: Arc<BwLimiter>,
 }
 
 impl Limiter {
-    /// This might need to take a Runtime, a clock type, or who
-    /// knows what else. Maybe we need a generalization of SleepProvider
-    /// that provides its own Instant and Duration types.
+    /// Create a new `Limiter` from `cfg`, and launch the background task that
+    /// refills its buckets and wakes its waiters on `runtime`.
+    pub fn new<R: Runtime>(cfg: &LimiterConfig, runtime: &R) -> Arc<Self> {
+        let r = BwLimiter::new(cfg.download_limit);
+        let w = BwLimiter::new(cfg.upload_limit);
+        r.launch_background_task(runtime.clone());
+        w.launch_background_task(runtime.clone());
+        Arc::new(Self { r, w })
+    }
+
+    /// Replace this limiter's configuration with `cfg`.
     ///
-    /// Ack; I think what we need is a generalization of a SleepProvider that defines its own Instant and Duration types.
-    pub fn new(cfg: &LimiterConfig) -> Arc<Self> {
-        Arc::new(Self {
-            r: BwLimiter::new(cfg.download_limit),
-            w: BwLimiter::new(cfg.upload_limit),
-        })
+    /// Affects every [`LimitedRead`]/[`LimitedWrite`] created from this
+    /// `Limiter`, including ones already in use.
+    pub fn reconfigure(&self, cfg: &LimiterConfig) {
+        self.r.reconfigure(cfg.download_limit);
+        self.w.reconfigure(cfg.upload_limit);
     }
-    /* TODO
-    pub fn reconfigure(&self, cfg: &LimitConfig) -> Result<(), ReconfigError> { todo!() }
-    */
 
-    /// All `LimitIo` from the same `Limiter` interact,
-    /// sharing the limit and using from kthe same quota.
+    /// All `LimitedRead`/`LimitedWrite` from the same `Limiter` interact,
+    /// sharing the limit and drawing from the same quota.
     pub fn limit_read<T>(&self, io: T) -> LimitedRead<T> {
         LimitedRead::new(self.r.clone(), io)
     }
+
+    /// As `limit_read`, but for the upload side.
+    pub fn limit_write<T>(&self, io: T) -> LimitedWrite<T> {
+        LimitedWrite::new(self.w.clone(), io)
+    }
+
+    /// Wrap a stream that implements both `AsyncRead` and `AsyncWrite` (such
+    /// as a `TcpStream` or `UnixStream`), throttling reads (ingress) against
+    /// this `Limiter`'s download quota and writes (egress) against its
+    /// upload quota.
+    pub fn limit<T>(&self, io: T) -> RateLimitedStream<T> {
+        RateLimitedStream::new(self.r.clone(), self.w.clone(), io)
+    }
+
+    /// Open a TCP connection to `addr` using `runtime`, and throttle it
+    /// with this `Limiter`.
+    //
+    // NOTE: this assumes `Runtime`'s supertraits include `TcpProvider`
+    // (as they do for every runtime backend in `tor-rtcompat`), so that
+    // `runtime.connect()` is available on a bare `R: Runtime`. We put this
+    // helper here, rather than on `TokioRuntimeHandle` in `tor-rtcompat`,
+    // because `tor-rtcompat` is a lower-layer crate that `tor-bw-ratelim`
+    // depends on; having it depend back on `tor-bw-ratelim` would be a
+    // layering cycle.
+    pub async fn connect_tcp<R: Runtime>(
+        &self,
+        runtime: &R,
+        addr: &SocketAddr,
+    ) -> IoResult<RateLimitedStream<<R as tor_rtcompat::TcpProvider>::TcpStream>> {
+        let stream = runtime.connect(addr).await?;
+        Ok(self.limit(stream))
+    }
+
+    /// Accept a TCP connection from `listener`, and throttle it with this
+    /// `Limiter`.
+    pub async fn accept_tcp<L: tor_rtcompat::TcpListener>(
+        &self,
+        listener: &L,
+    ) -> IoResult<(RateLimitedStream<L::TcpStream>, SocketAddr)> {
+        let (stream, addr) = listener.accept().await?;
+        Ok((self.limit(stream), addr))
+    }
+
+    /// Open a connection to the Unix socket at `path` using `runtime`, and
+    /// throttle it with this `Limiter`.
+    pub async fn connect_unix<R: Runtime>(
+        &self,
+        runtime: &R,
+        path: &Path,
+    ) -> IoResult<RateLimitedStream<<R as tor_rtcompat::UnixProvider>::UnixStream>> {
+        let stream = runtime.connect_unix(path).await?;
+        Ok(self.limit(stream))
+    }
+
+    /// Accept a connection from a Unix socket `listener`, and throttle it
+    /// with this `Limiter`.
+    pub async fn accept_unix<L: tor_rtcompat::UnixListener>(
+        &self,
+        listener: &L,
+    ) -> IoResult<(RateLimitedStream<L::UnixStream>, tor_rtcompat::UnixSocketAddr)> {
+        let (stream, addr) = listener.accept().await?;
+        Ok((self.limit(stream), addr))
+    }
 }
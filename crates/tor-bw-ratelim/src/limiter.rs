@@ -1,11 +1,13 @@
 use std::{
-    rc::Weak,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
 };
 
-use tor_rtcompat::Runtime;
+use futures::task::SpawnExt as _;
+use tor_rtcompat::{Runtime, SleepProvider as _};
 
-use crate::TrafficRateLimit;
+use crate::wait_list::WaitList;
+use crate::{Granularity, TrafficRateLimit};
 
 // Approximate number of bytes needed to write a cell to the network, including
 // TLS overhead.
@@ -13,88 +15,232 @@ use crate::TrafficRateLimit;
 // Does not need to be precise; this is an overestimate.
 const BYTES_PER_CELL: usize = 576;
 
+// We never let the background task sleep for less than this, so that a very
+// large `max_bytes_per_sec` (and hence a tiny `increment`) can't make it spin.
+const MIN_TICK: Duration = Duration::from_millis(10);
+// Nor do we let it sleep for longer than this, so that a limiter configured
+// with a very small `max_bytes_per_sec` still notices refills reasonably
+// promptly after `reconfigure()`.
+const MAX_TICK: Duration = Duration::from_secs(1);
+
+/// State for a GCRA (Generic Cell Rate Algorithm) rate limiter.
+///
+/// GCRA is equivalent to a token bucket, but instead of tracking a level that
+/// needs periodic refilling, it tracks a single `theoretical_arrival_time`
+/// (TAT): the time at which the bucket would be exactly full, given every
+/// grant made so far. A request conforms if granting it wouldn't push the
+/// TAT further into the future than `limit` (our burst allowance, expressed
+/// as a duration) past now.
 struct LimiterInner {
-    // How much time must pass before we are ready to add a single cell to the
-    // queue?
-    dur_per_cell: coarsetime::Duration,
+    // How many bytes per second are we willing to hand out, in steady state?
+    max_bytes_per_sec: u64,
     // How many bytes are we willing to send per burst?
     bytes_burst: usize,
+    // Whether grants must be rounded down to a cell boundary.
+    granularity: Granularity,
 
-    // How many bytes of capacity do we have?
-    //
-    // NOTE: In reality, it would make sense to use a GCRA algorithm here like
-    // governor does.  We need to decide whether the GCRA algorithm is based on
-    // bytes or on cells.  If it's based on cells, we should retain an
-    // additional "slop" for unused bytes above or below the cell increment.
-    cur_level: usize,
+    // The time cost of one `BYTES_PER_CELL`-sized chunk of traffic, at
+    // `max_bytes_per_sec`.
+    increment: Duration,
+    // The time-domain equivalent of `bytes_burst`: how far into the future
+    // `theoretical_arrival_time` is allowed to run ahead of "now" before a
+    // request stops conforming.
+    limit: Duration,
+    // The GCRA theoretical arrival time.
+    theoretical_arrival_time: coarsetime::Instant,
+
+    // How long should the background task sleep between notifications?
+    tick: Duration,
 
     // An event that we'll use to notify waiters when we have more capacity.
+    //
+    // Kept as a fallback for `take_bytes()`, for callers without a `Context`
+    // to register a waker with; `wait_list` is used by everyone else.
     event: event_listener::Event,
+
+    // Allocation-free FIFO list of wakers for `poll_take_bytes()` callers.
+    pub(crate) wait_list: WaitList,
+}
+
+impl LimiterInner {
+    fn new(lim: TrafficRateLimit) -> Self {
+        let increment = dur_per_cell(lim.max_bytes_per_sec);
+        let bytes_burst = lim.max_bytes_burst as usize;
+        LimiterInner {
+            max_bytes_per_sec: lim.max_bytes_per_sec,
+            bytes_burst,
+            granularity: lim.granularity,
+            limit: cost_for(bytes_burst, increment),
+            increment,
+            theoretical_arrival_time: coarsetime::Instant::now(),
+            tick: tick_interval(increment),
+            event: event_listener::Event::new(),
+            wait_list: WaitList::default(),
+        }
+    }
+
+    fn reconfigure(&mut self, lim: TrafficRateLimit) {
+        self.max_bytes_per_sec = lim.max_bytes_per_sec;
+        self.bytes_burst = lim.max_bytes_burst as usize;
+        self.granularity = lim.granularity;
+        self.increment = dur_per_cell(lim.max_bytes_per_sec);
+        self.limit = cost_for(self.bytes_burst, self.increment);
+        self.tick = tick_interval(self.increment);
+    }
+
+    /// How many bytes could be granted right now, at `now`, without the
+    /// grant failing to conform?
+    fn available_bytes(&self, now: coarsetime::Instant) -> usize {
+        // A TAT that's already in the past is exactly as good as a TAT of
+        // `now`: either way, the full burst `limit` is available.
+        let tat = self.theoretical_arrival_time.max(now);
+        let budget = now
+            .checked_add(self.limit)
+            .and_then(|deadline| deadline.checked_duration_since(tat))
+            .unwrap_or(Duration::ZERO);
+        let increment_nanos = self.increment.as_nanos().max(1);
+        let whole_cells = budget.as_nanos() / increment_nanos;
+        let bytes = whole_cells.saturating_mul(BYTES_PER_CELL as u128);
+        usize::try_from(bytes)
+            .unwrap_or(usize::MAX)
+            .min(self.bytes_burst)
+    }
+
+    /// Try to grant up to `n` bytes, respecting `self.granularity`.
+    ///
+    /// On success, advances `theoretical_arrival_time` by the cost of the
+    /// granted amount and returns it. Returns `None` if nothing can be
+    /// granted right now.
+    pub(crate) fn grant(&mut self, n: usize) -> Option<usize> {
+        let now = coarsetime::Instant::now();
+        let available = self.available_bytes(now);
+
+        let granted = match self.granularity {
+            Granularity::Byte => {
+                if available == 0 {
+                    return None;
+                }
+                n.min(available)
+            }
+            Granularity::Cell => {
+                if n <= available {
+                    // If we can satisfy all of this request, then do so.
+                    n
+                } else if available >= BYTES_PER_CELL {
+                    // If we can satisfy BYTES_PER_CELL, then do so.
+                    BYTES_PER_CELL
+                } else {
+                    // Otherwise, the request wants to write at least
+                    // BYTES_PER_CELL, and we don't have that much quota.
+                    return None;
+                }
+            }
+        };
+
+        let cost = cost_for(granted, self.increment);
+        self.theoretical_arrival_time = self.theoretical_arrival_time.max(now) + cost;
+        Some(granted)
+    }
+}
+
+/// Compute `increment`: the time it takes to "earn" one `BYTES_PER_CELL`
+/// chunk of bandwidth at `max_bytes_per_sec`.
+///
+/// Uses `u128` nanosecond arithmetic, rather than casting through `u32`, so
+/// that `max_bytes_per_sec` values above ~4 GB/s don't silently wrap around.
+fn dur_per_cell(max_bytes_per_sec: u64) -> Duration {
+    if max_bytes_per_sec == 0 {
+        // No rate at all: no amount of elapsed time should ever earn us
+        // another grant.
+        return Duration::MAX;
+    }
+    let nanos =
+        (BYTES_PER_CELL as u128 * 1_000_000_000_u128) / (max_bytes_per_sec as u128);
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+/// The GCRA "cost", in time, of `n` bytes of traffic: `increment` for every
+/// `BYTES_PER_CELL` bytes, rounded up.
+fn cost_for(n: usize, increment: Duration) -> Duration {
+    let cells = n.saturating_add(BYTES_PER_CELL - 1) / BYTES_PER_CELL;
+    increment
+        .checked_mul(cells as u32)
+        .unwrap_or(Duration::MAX)
+}
+
+/// Choose how often the background task should wake up to notify waiters.
+///
+/// We wake often enough to grant a single cell's worth of bytes promptly, but
+/// not so often that we burn CPU on a connection with a tiny limit.
+fn tick_interval(increment: Duration) -> Duration {
+    increment.clamp(MIN_TICK, MAX_TICK)
 }
 
 pub(crate) struct BwLimiter {
-    inner: Mutex<LimiterInner>,
+    pub(crate) inner: Mutex<LimiterInner>,
 }
 
 impl BwLimiter {
     pub fn new(lim: TrafficRateLimit) -> Arc<Self> {
-        let dur_per_cell = coarsetime::Duration::from_secs(1) * (BYTES_PER_CELL as u32)
-            / (lim.max_bytes_per_sec as u32); // XXXX this cast is totally wrong.
-
         Arc::new(BwLimiter {
-            inner: Mutex::new(LimiterInner {
-                dur_per_cell,
-                bytes_burst: lim.max_bytes_burst as usize, // XXXX also a bad cast.
-                cur_level: 0,
-                event: event_listener::Event::new(),
-            }),
+            inner: Mutex::new(LimiterInner::new(lim)),
         })
     }
 
-    pub fn launch_background_task<R: Runtime>(self: &Arc<Self>, _runtime: R) {
-        todo!()
+    pub fn launch_background_task<R: Runtime>(self: &Arc<Self>, runtime: R) {
+        let weak = Arc::downgrade(self);
+        // If we can't spawn the notification task, we don't panic; the
+        // limiter then never wakes a waiter on its own, and waiters are only
+        // woken by a `reclaim()` or `reconfigure()` call.
+        let _ = runtime.clone().spawn(background_task(weak, runtime));
     }
 
-    /// Return `n` unused bytes to the current bucket.
+    /// Update this limiter's rate, burst size, and granularity in place.
+    ///
+    /// Existing [`Permit`]s are unaffected; only the rate and burst limit
+    /// used for future requests change.
+    pub fn reconfigure(&self, lim: TrafficRateLimit) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        inner.reconfigure(lim);
+        // Some waiters may now be satisfiable under the new, possibly larger,
+        // burst ceiling or rate; let them re-check.
+        inner.event.notify(usize::MAX);
+        inner.wait_list.drain_wake();
+    }
+
+    /// Return `n` unused bytes, refunding the time cost we charged for them.
     ///
     /// This can violate our limits unless you have previously received
-    /// permission to read this many bytes.
-    fn put_back(&self, n: usize) {
+    /// permission to read or write this many bytes.
+    fn reclaim(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
         let mut inner = self.inner.lock().expect("poisoned lock");
-        inner.cur_level = inner.cur_level.saturating_add(n).min(inner.bytes_burst);
+        let now = coarsetime::Instant::now();
+        let refund = cost_for(n, inner.increment);
+        inner.theoretical_arrival_time = inner
+            .theoretical_arrival_time
+            .checked_sub(refund)
+            .unwrap_or(now);
+        inner.event.notify(usize::MAX);
+        inner.wait_list.drain_wake();
     }
 
     /// Submit a request to consume `n` bytes.  On success, return a number of
     /// bytes no greater than `n` which we may consume.  On failure,
     /// return an event that we should wait for before asking again.
+    ///
+    /// This is a thin fallback, kept for callers with no `Context` to
+    /// register a waker with; callers that do have one (i.e. that are
+    /// themselves inside a `poll_*` method) should prefer
+    /// [`WaitNode::poll_take_bytes`](crate::wait_list::WaitNode::poll_take_bytes),
+    /// which doesn't need to allocate an `EventListener` per throttle.
     pub(crate) fn take_bytes(&self, n: usize) -> Result<Permit<'_>, event_listener::EventListener> {
         let mut inner = self.inner.lock().expect("poisoned lock");
-        if n <= inner.cur_level {
-            // If we can satisfy all of this request, then do so.
-            //
-            // XXXX (but if there is contention, we might not want to do return
-            // more then BYTES_PER_CELL!)
-            inner.cur_level -= n;
-            Ok(Permit { n, limiter: self })
-        } else if BYTES_PER_CELL <= inner.cur_level {
-            // If we can satisfy BYTES_PER_CELL, then do so.
-            debug_assert!(BYTES_PER_CELL < n);
-            inner.cur_level -= BYTES_PER_CELL;
-            Ok(Permit {
-                n: BYTES_PER_CELL,
-                limiter: self,
-            })
-        } else {
-            // Otherwise, the request wants to write at least BYTES_PER_CELL,
-            // and we don't have that much quota.
-
-            // TODO: Perhaps, tell the background task in this case that it
-            // should start its timer if it has not done so already.
-
-            // TODO: This returns an event_listener, which is heap-allocated.
-            // It might be better to have take_bytes function take a cx as an argument
-            // and store a Waker.
-            Err(inner.event.listen())
+        match inner.grant(n) {
+            Some(granted) => Ok(Permit::new(granted, self)),
+            None => Err(inner.event.listen()),
         }
     }
 }
@@ -111,25 +257,47 @@ pub(crate) struct Permit<'a> {
 }
 
 impl<'a> Permit<'a> {
+    /// Construct a permit for `n` bytes granted by `limiter`.
+    pub(crate) fn new(n: usize, limiter: &'a BwLimiter) -> Self {
+        Permit { n, limiter }
+    }
+
     // Report the amount of bytes from this permit that have actually been used.
     pub(crate) fn used(mut self, used: usize) {
-        debug_assert!(used < self.n);
-        self.limiter.put_back(self.n - used);
+        debug_assert!(used <= self.n);
+        self.limiter.reclaim(self.n - used);
         self.n = 0;
     }
 
     // Report that no amount of this permit was actually used.
     pub(crate) fn unused(mut self) {
-        self.limiter.put_back(self.n);
+        self.limiter.reclaim(self.n);
         self.n = 0;
     }
 }
 
-fn background_task(_limiter: Weak<BwLimiter>) {
+/// Body of the background task that periodically wakes whichever waiters
+/// have become satisfiable as time has passed.
+///
+/// Exits as soon as `limiter` has no more strong references, so a `Limiter`
+/// going away doesn't leave a task spinning forever.
+async fn background_task<R: Runtime>(limiter: Weak<BwLimiter>, runtime: R) {
     loop {
-        // - as time elapses, refill buffer
-        // - notify waiters.
-        // - When waiting, be sure to wait at least for as long as dur_per_cell.
-        todo!()
+        let tick = match limiter.upgrade() {
+            Some(l) => l.inner.lock().expect("poisoned lock").tick,
+            None => return,
+        };
+        runtime.sleep(tick).await;
+        match limiter.upgrade() {
+            Some(l) => {
+                let mut inner = l.inner.lock().expect("poisoned lock");
+                // GCRA conformance is computed fresh from elapsed time on
+                // every `take_bytes()` call, so there's no state to refill
+                // here -- we just need to wake anyone who might now conform.
+                inner.event.notify(usize::MAX);
+                inner.wait_list.drain_wake();
+            }
+            None => return,
+        }
     }
 }
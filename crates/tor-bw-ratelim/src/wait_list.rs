@@ -0,0 +1,177 @@
+//! An intrusive, allocation-free FIFO wait list of [`Waker`]s.
+//!
+//! [`BwLimiter::take_bytes`](crate::limiter::BwLimiter::take_bytes) has to
+//! heap-allocate an `event_listener::EventListener` on every throttle, which
+//! is wasteful on the hot per-cell path. [`WaitNode`] gives callers that
+//! already have a `Context` (as every `poll_read`/`poll_write` does) a way to
+//! wait without allocating: the node lives in the caller's own pinned future
+//! state, and is only ever touched while the owning limiter's `Mutex` is
+//! held. Modeled loosely on tokio's `scheduled_io` intrusive waiter list.
+
+use std::marker::PhantomPinned;
+use std::pin::Pin;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use crate::limiter::{BwLimiter, Permit};
+
+/// A node in the intrusive wait list.
+///
+/// Must stay pinned for as long as it might be linked into its limiter's
+/// wait list: the list stores its address, and `Drop` unlinks it, so moving
+/// a linked node would corrupt the list.
+pub(crate) struct WaitNode {
+    /// The limiter this node waits on.
+    limiter: Arc<BwLimiter>,
+    /// The waker to call when this node is woken, if any is registered.
+    waker: Option<Waker>,
+    /// The previous node in the list, if any.
+    prev: Option<NonNull<WaitNode>>,
+    /// The next node in the list, if any.
+    next: Option<NonNull<WaitNode>>,
+    /// Whether this node is currently linked into its limiter's wait list.
+    linked: bool,
+    _pin: PhantomPinned,
+}
+
+// SAFETY: every access to a `WaitNode` through the raw pointers stored in a
+// `WaitList` -- and every mutation of a `WaitNode`'s own fields, including
+// from `poll_take_bytes` and `Drop` -- happens only while the owning
+// `BwLimiter`'s `Mutex` is held. That's the same invariant a `Mutex<T>`
+// itself relies on to make `T: Send` sufficient for sharing across threads.
+unsafe impl Send for WaitNode {}
+
+impl WaitNode {
+    /// Create a new, unlinked wait node for requests against `limiter`.
+    pub(crate) fn new(limiter: Arc<BwLimiter>) -> Self {
+        WaitNode {
+            limiter,
+            waker: None,
+            prev: None,
+            next: None,
+            linked: false,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Try to take `n` bytes from this node's limiter.
+    ///
+    /// On success, unlinks this node (if it was linked) and returns the
+    /// granted [`Permit`]. On failure, (re-)registers `cx`'s waker in the
+    /// limiter's intrusive wait list -- replacing any previously registered
+    /// waker in place -- and returns `Pending`.
+    pub(crate) fn poll_take_bytes(
+        self: Pin<&mut Self>,
+        n: usize,
+        cx: &mut Context<'_>,
+    ) -> Poll<Permit<'_>> {
+        // SAFETY: we never move `*this`; it stays pinned for as long as it
+        // might be linked (its `Drop` impl unlinks it first).
+        let this = unsafe { self.get_unchecked_mut() };
+        let limiter: &BwLimiter = &this.limiter;
+        let mut inner = limiter.inner.lock().expect("poisoned lock");
+
+        match inner.grant(n) {
+            Some(granted) => {
+                inner.wait_list.remove(this);
+                drop(inner);
+                Poll::Ready(Permit::new(granted, limiter))
+            }
+            None => {
+                inner.wait_list.register(this, cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for WaitNode {
+    fn drop(&mut self) {
+        // `linked` is only read under the lock: `drain_wake` may be clearing
+        // it from another thread.
+        let limiter = Arc::clone(&self.limiter);
+        let mut inner = limiter.inner.lock().expect("poisoned lock");
+        inner.wait_list.remove(self);
+    }
+}
+
+/// An intrusive doubly-linked FIFO list of [`WaitNode`]s.
+///
+/// Storage for each node lives in the node itself (in some caller's pinned
+/// future), not here: this type only ever holds head/tail pointers, so
+/// registering a waiter never allocates.
+#[derive(Default)]
+pub(crate) struct WaitList {
+    head: Option<NonNull<WaitNode>>,
+    tail: Option<NonNull<WaitNode>>,
+}
+
+// SAFETY: as with `WaitNode`'s `Send` impl, every dereference of a pointer
+// stored here happens only while the owning limiter's `Mutex` is held.
+unsafe impl Send for WaitList {}
+
+impl WaitList {
+    /// Register (or re-register, with a fresh `Waker`) `node` at the back of
+    /// the list. Re-registering an already-linked node replaces its stored
+    /// waker in place, without moving it within the list.
+    fn register(&mut self, node: &mut WaitNode, waker: Waker) {
+        if node.linked {
+            node.waker = Some(waker);
+            return;
+        }
+        node.waker = Some(waker);
+        node.prev = self.tail;
+        node.next = None;
+        let ptr = NonNull::from(&mut *node);
+        match self.tail {
+            // SAFETY: every pointer stored in this list points at a
+            // `WaitNode` that is still alive and pinned: a node removes
+            // itself (via `Drop`) before it could become dangling.
+            Some(tail) => unsafe { (*tail.as_ptr()).next = Some(ptr) },
+            None => self.head = Some(ptr),
+        }
+        self.tail = Some(ptr);
+        node.linked = true;
+    }
+
+    /// Remove `node` from the list. A no-op if it isn't linked.
+    fn remove(&mut self, node: &mut WaitNode) {
+        if !node.linked {
+            return;
+        }
+        // SAFETY: see `register`.
+        unsafe {
+            match node.prev {
+                Some(prev) => (*prev.as_ptr()).next = node.next,
+                None => self.head = node.next,
+            }
+            match node.next {
+                Some(next) => (*next.as_ptr()).prev = node.prev,
+                None => self.tail = node.prev,
+            }
+        }
+        node.prev = None;
+        node.next = None;
+        node.linked = false;
+        node.waker = None;
+    }
+
+    /// Drain every node from the list, in FIFO order, waking each one.
+    pub(crate) fn drain_wake(&mut self) {
+        let mut cur = self.head.take();
+        self.tail = None;
+        while let Some(ptr) = cur {
+            // SAFETY: see `register`; every node in the list is unlinked by
+            // this loop, so no dangling pointers remain once it ends.
+            let node = unsafe { &mut *ptr.as_ptr() };
+            cur = node.next;
+            node.prev = None;
+            node.next = None;
+            node.linked = false;
+            if let Some(waker) = node.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
@@ -6,9 +6,11 @@
 
 mod connect;
 mod err;
+mod intro_race;
 mod isol_map;
 mod keys;
 mod pow;
+mod progress;
 mod proto_oneshot;
 mod relay_info;
 mod state;
@@ -33,7 +35,9 @@ use tor_rtcompat::Runtime;
 
 pub use err::FailedAttemptError;
 pub use err::{ConnError, DescriptorError, DescriptorErrorDetail, StartupError};
+pub use intro_race::IntroParallelismConfig;
 pub use keys::{HsClientDescEncKeypairSpecifier, HsClientSecretKeys, HsClientSecretKeysBuilder};
+pub use progress::HsConnProgress;
 pub use relay_info::InvalidTarget;
 pub use state::HsClientConnectorConfig;
 
@@ -132,6 +136,46 @@ impl<R: Runtime> HsClientConnector<R, connect::Data> {
         Services::get_or_launch_connection(self, netdir, hs_id, isolation, secret_keys)
     }
 
+    /// Connect to a hidden service, reporting coarse progress as we go.
+    ///
+    /// Drives the same attempt as [`get_or_launch_circuit`](Self::get_or_launch_circuit),
+    /// alongside a stream of [`HsConnProgress`] events. The returned stream
+    /// ends, without necessarily yielding anything, once the attempt
+    /// finishes (a caller that only wants the outcome can just drop it).
+    ///
+    /// DEFERRED: today this can only observe the *outcome* of the attempt --
+    /// [`Connected`](HsConnProgress::Connected) on success, or
+    /// [`IntroFailed`](HsConnProgress::IntroFailed) on failure -- because the
+    /// descriptor-fetch/introduction state machine that would report the
+    /// mid-flight transitions (`connect.rs`/`state.rs`) isn't part of this
+    /// crate snapshot, so there is nothing to hand a [`progress::ProgressSender`]
+    /// to partway through. See [`progress`] for the full set of events this
+    /// is meant to eventually report.
+    pub fn get_or_launch_circuit_with_events<'r>(
+        &'r self,
+        netdir: &'r Arc<NetDir>,
+        hs_id: HsId,
+        secret_keys: HsClientSecretKeys,
+        isolation: StreamIsolation,
+    ) -> (
+        impl Future<Output = Result<Arc<ClientCirc>, ConnError>> + Send + Sync + 'r,
+        BoxStream<'static, HsConnProgress>,
+    ) {
+        let (mut progress_tx, progress_rx) = progress::channel();
+        let inner = self.get_or_launch_circuit(netdir, hs_id, secret_keys, isolation);
+        let future = async move {
+            let result = inner.await;
+            match &result {
+                Ok(_) => progress_tx.report(HsConnProgress::Connected),
+                Err(e) => progress_tx.report(HsConnProgress::IntroFailed {
+                    reason: e.to_string(),
+                }),
+            }
+            result
+        };
+        (future, progress_rx)
+    }
+
     /// A deprecated alias for `get_or_launch_circuit`.
     ///
     /// We renamed it to be
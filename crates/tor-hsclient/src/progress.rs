@@ -0,0 +1,85 @@
+//! Progress events for in-flight onion-service connection attempts.
+//!
+//! [`HsClientConnector::get_or_launch_circuit`](crate::HsClientConnector::get_or_launch_circuit)
+//! only reports a final result; [`HsConnProgress`] lets a caller that wants
+//! to show bootstrap-style progress (descriptor fetch, introduction-point
+//! retries, rendezvous setup) subscribe to state transitions along the way.
+//!
+//! DEFERRED: only the two states actually wired up --
+//! [`get_or_launch_circuit_with_events`](crate::HsClientConnector::get_or_launch_circuit_with_events)'s
+//! terminal [`Connected`](HsConnProgress::Connected) and
+//! [`IntroFailed`](HsConnProgress::IntroFailed) -- are ever reported today.
+//! The mid-flight variants below ([`FetchingDescriptor`](HsConnProgress::FetchingDescriptor),
+//! [`GotDescriptor`](HsConnProgress::GotDescriptor),
+//! [`ContactingIntroPt`](HsConnProgress::ContactingIntroPt),
+//! [`EstablishingRendezvous`](HsConnProgress::EstablishingRendezvous)) describe
+//! transitions inside the descriptor-fetch/introduction state machine, which
+//! lives in `connect.rs`/`state.rs` -- not part of this crate snapshot -- so
+//! there is nothing here that can actually report them yet. Reporting them
+//! for real needs `connect`/`state` to accept a [`ProgressSender`] and call
+//! [`ProgressSender::report`] at each stage; until then, those variants stay
+//! defined (so downstream matches don't need to change again once they are
+//! wired) but unreachable.
+
+use futures::channel::mpsc;
+use futures::stream::BoxStream;
+use futures::StreamExt as _;
+
+/// A state transition reported while connecting to an onion service.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum HsConnProgress {
+    /// We are fetching the onion service's descriptor from an HsDir.
+    FetchingDescriptor,
+    /// We have a descriptor, and are about to start contacting
+    /// introduction points.
+    GotDescriptor,
+    /// We are attempting to use introduction point number `index`
+    /// (counting from the descriptor's own ordering).
+    ContactingIntroPt {
+        /// Index, within the descriptor, of the introduction point.
+        index: usize,
+    },
+    /// An attempt against an introduction point failed; we may retry with
+    /// another.
+    ///
+    /// [`get_or_launch_circuit_with_events`](crate::HsClientConnector::get_or_launch_circuit_with_events)
+    /// also reports this, as its final event, when the whole connection
+    /// attempt fails for any reason.
+    IntroFailed {
+        /// A human-readable description of why the attempt failed.
+        reason: String,
+    },
+    /// Introduction succeeded; we are completing the rendezvous circuit.
+    EstablishingRendezvous,
+    /// The connection succeeded.
+    Connected,
+}
+
+/// Internal handle used to report [`HsConnProgress`] events as they happen.
+///
+/// Cloneable and cheap; reporting an event when nobody is listening (or
+/// after the subscriber has been dropped) is a silent no-op.
+#[derive(Clone, Debug)]
+pub(crate) struct ProgressSender(mpsc::Sender<HsConnProgress>);
+
+impl ProgressSender {
+    /// Report that `event` has happened.
+    pub(crate) fn report(&mut self, event: HsConnProgress) {
+        // A full channel or a dropped receiver both just mean nobody's
+        // listening (closely); either way there's nothing a reporter can
+        // usefully do about it.
+        let _ = self.0.try_send(event);
+    }
+}
+
+/// Create a fresh [`ProgressSender`]/[`BoxStream`] pair for a single
+/// connection attempt.
+///
+/// The channel is small and bounded: progress events are advisory, and a
+/// slow or absent subscriber shouldn't be able to apply backpressure to the
+/// connection attempt itself.
+pub(crate) fn channel() -> (ProgressSender, BoxStream<'static, HsConnProgress>) {
+    let (tx, rx) = mpsc::channel(16);
+    (ProgressSender(tx), rx.boxed())
+}
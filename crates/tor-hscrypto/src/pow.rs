@@ -0,0 +1,232 @@
+//! Support for the v3 onion-service proof-of-work (PoW) defense described in
+//! `prop327` / rend-spec-v3's `pow-params` line.
+//!
+//! Only the *client* side (finding a solution) lives here; the *service*
+//! side (choosing a seed and effort, and checking a client's solution) is out
+//! of scope for this module so far.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use blake2::{digest::consts::U32, Blake2b, Digest};
+
+/// Personalization string mixed into the Equi-X challenge, per rend-spec-v3.
+const PERSONALIZATION: &[u8] = b"Tor hs pow v1";
+
+/// Length in bytes of the challenge string fed to Equi-X: personalization,
+/// followed by a 32-byte seed, a 16-byte nonce, and a 4-byte big-endian
+/// effort.
+const CHALLENGE_LEN: usize = PERSONALIZATION.len() + 32 + 16 + 4;
+
+/// A service-chosen seed for its current proof-of-work parameters.
+///
+/// Changes periodically; a solution computed against a stale seed is
+/// rejected by the service.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Seed([u8; 32]);
+
+impl Seed {
+    /// Wrap a raw 32-byte seed, as parsed from a `pow-params` line.
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Return the underlying bytes of this seed.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// A client-chosen nonce.
+///
+/// Randomized at the start of solving, then incremented after every attempt
+/// that fails to meet the required effort.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Nonce([u8; 16]);
+
+impl Nonce {
+    /// Choose a new random nonce to start solving with.
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    /// Return the underlying bytes of this nonce.
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// Return the nonce that follows this one, wrapping on overflow.
+    ///
+    /// We only need these nonces to be distinct from one another across a
+    /// single solve; wrapping around is harmless.
+    #[must_use]
+    fn incremented(self) -> Self {
+        let mut bytes = self.0;
+        for byte in bytes.iter_mut().rev() {
+            let (next, overflowed) = byte.overflowing_add(1);
+            *byte = next;
+            if !overflowed {
+                break;
+            }
+        }
+        Self(bytes)
+    }
+}
+
+/// How hard a proof-of-work solution must be to find.
+///
+/// Units are chosen so that, in expectation, doubling the effort doubles the
+/// number of Equi-X attempts a solver needs to make.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, derive_more::From, derive_more::Into)]
+pub struct Effort(u32);
+
+impl Effort {
+    /// Clamp this effort so that it is never larger than `max`.
+    ///
+    /// Callers should always apply some maximum: without one, a malicious or
+    /// misconfigured service could demand an arbitrarily expensive solution.
+    #[must_use]
+    pub fn clamped(self, max: Effort) -> Effort {
+        self.min(max)
+    }
+}
+
+/// A completed proof-of-work solution, ready to be embedded in an
+/// `INTRODUCE1` cell's `PROOF_OF_WORK` extension.
+#[derive(Clone, Debug)]
+pub struct ProofOfWork {
+    /// The seed the solution was computed against.
+    seed: Seed,
+    /// The nonce that produced an accepted solution.
+    nonce: Nonce,
+    /// The effort the solution satisfies.
+    effort: Effort,
+    /// The raw Equi-X solution bytes.
+    solution: equix::SolutionByteArray,
+}
+
+impl ProofOfWork {
+    /// The seed this solution was computed against.
+    pub fn seed(&self) -> Seed {
+        self.seed
+    }
+    /// The nonce that produced this solution.
+    pub fn nonce(&self) -> Nonce {
+        self.nonce
+    }
+    /// The effort this solution satisfies.
+    pub fn effort(&self) -> Effort {
+        self.effort
+    }
+    /// The raw Equi-X solution bytes.
+    pub fn solution_bytes(&self) -> &equix::SolutionByteArray {
+        &self.solution
+    }
+}
+
+/// Build the byte string that the Equi-X challenge, and the final
+/// acceptance hash, are both computed over:
+/// `"Tor hs pow v1" || seed || nonce || effort`.
+///
+/// Including `effort` here (and not just in the final acceptance hash)
+/// means a solution is tied to the effort it was solved at: a solver can't
+/// find a solution at a low effort and then claim it also satisfies a
+/// higher one, since a different effort produces a different Equi-X
+/// instance entirely.
+fn challenge(seed: &Seed, nonce: &Nonce, effort: Effort) -> [u8; CHALLENGE_LEN] {
+    let mut buf = [0_u8; CHALLENGE_LEN];
+    let (pers, rest) = buf.split_at_mut(PERSONALIZATION.len());
+    let (s, rest) = rest.split_at_mut(32);
+    let (n, e) = rest.split_at_mut(16);
+    pers.copy_from_slice(PERSONALIZATION);
+    s.copy_from_slice(seed.as_bytes());
+    n.copy_from_slice(nonce.as_bytes());
+    e.copy_from_slice(&u32::from(effort).to_be_bytes());
+    buf
+}
+
+/// Return whether `solution`, found against `challenge`, meets `effort`.
+///
+/// Per rend-spec-v3: interpret `blake2b_256(challenge || solution)` as a
+/// 256-bit big-endian integer `V`, and accept iff `V * effort <= 2^256`.
+fn solution_meets_effort(challenge: &[u8], solution: &[u8], effort: Effort) -> bool {
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update(challenge);
+    hasher.update(solution);
+    let digest: [u8; 32] = hasher.finalize().into();
+    equix::digest_meets_effort(&digest, effort.into())
+}
+
+/// Try to solve a proof-of-work challenge for `seed` at exactly `nonce`,
+/// accepted at `effort`.
+///
+/// Returns `None` if none of `nonce`'s Equi-X solutions meet `effort`.
+///
+/// Unlike [`solve`], this makes only one attempt and never loops, so callers
+/// that need to bound the total time spent solving (or otherwise want to
+/// interleave solving with their own cancellation or progress checks) should
+/// call this in their own loop, picking a fresh nonce (for example with
+/// [`Nonce::random`]) between calls, instead of using [`solve`].
+pub fn try_solve_once(seed: Seed, nonce: Nonce, effort: Effort) -> Option<ProofOfWork> {
+    let chal = challenge(&seed, &nonce, effort);
+    // A small fraction of challenges are rejected outright with
+    // `HashError::ProgramConstraints`; per equix's own docs, solvers are
+    // expected to just skip these rather than treat them as an error.
+    let Ok(solutions) = equix::EquiXBuilder::new().solve(&chal) else {
+        return None;
+    };
+    // NOTE: this assumes `Solution` exposes a `to_bytes()` producing the
+    // canonical `SolutionByteArray`, mirroring the `Solution::try_from_bytes`
+    // direction already used by `EquiXBuilder::verify_bytes`.
+    for solution in solutions.iter() {
+        let bytes = solution.to_bytes();
+        if solution_meets_effort(&chal, &bytes, effort) {
+            return Some(ProofOfWork {
+                seed,
+                nonce,
+                effort,
+                solution: bytes,
+            });
+        }
+    }
+    None
+}
+
+/// Search for a proof-of-work solution for `seed`, accepted at `effort`
+/// (clamped to `max_effort`).
+///
+/// Starts from a random nonce and increments it after every attempt that
+/// fails to meet the required effort, trying again until one succeeds.
+pub fn solve(seed: Seed, effort: Effort, max_effort: Effort) -> ProofOfWork {
+    solve_cancellable(seed, effort, max_effort, &AtomicBool::new(false))
+        .expect("solve_cancellable returned None with a cancel flag that's never set")
+}
+
+/// As [`solve`], but checks `cancel` between attempts and gives up, returning
+/// `None`, the moment it reads `true`.
+///
+/// Each attempt (a single Equi-X solve at one nonce) always runs to
+/// completion once started -- the same limit `equix::EquiX::
+/// solve_with_memory_cancellable` documents -- but since every attempt here
+/// is already a bounded, self-contained unit of work, checking `cancel`
+/// between attempts is enough for a caller to actually abandon solving
+/// (for example, because the service became reachable through a
+/// lower-effort path) instead of `solve`'s unconditional loop, which gave a
+/// caller no way to stop it short of blocking forever.
+pub fn solve_cancellable(
+    seed: Seed,
+    effort: Effort,
+    max_effort: Effort,
+    cancel: &AtomicBool,
+) -> Option<ProofOfWork> {
+    let effort = effort.clamped(max_effort);
+    let mut nonce = Nonce::random();
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        if let Some(pow) = try_solve_once(seed, nonce, effort) {
+            return Some(pow);
+        }
+        nonce = nonce.incremented();
+    }
+}
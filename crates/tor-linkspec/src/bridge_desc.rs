@@ -0,0 +1,167 @@
+//! Parsing and formatting for torrc-style "bridge lines".
+//!
+//! A bridge line is the one-line format torrc's `Bridge` option (and bridge
+//! lists downloaded from BridgeDB) use to describe a single bridge relay:
+//! an optional pluggable-transport name, an address, one or two relay
+//! fingerprints, and (for a transport) trailing `key=value` settings. For
+//! example:
+//!
+//! ```text
+//! obfs4 1.2.3.4:443 0123456789ABCDEF0123456789ABCDEF01234567 cert=AAAA iat-mode=0
+//! 1.2.3.4:9001 0123456789ABCDEF0123456789ABCDEF01234567 dGhpc2lzYWZha2VlZDI1NTE5a2V5
+//! ```
+//!
+//! NOTE: `owned.rs`, `transport.rs`, and `ids.rs` are not present in this
+//! checkout, so the precise shapes of [`OwnedChanTargetBuilder`],
+//! [`RelayIds`]'s [`FromIterator`] impl, and [`PtTargetSettings::new`] are
+//! assumed from their public re-exports here, rather than read from source.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use crate::{
+    ChannelMethod, HasChanMethod, HasRelayIds, OwnedChanTarget, OwnedChanTargetBuilder, PtTarget,
+    PtTargetSettings, PtTransportName, RelayId, RelayIdType, RelayIds,
+};
+
+/// An error encountered while parsing a bridge line.
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum BridgeLineParseError {
+    /// The line had no tokens at all.
+    #[error("bridge line was empty")]
+    Empty,
+    /// The line named a transport but had no address after it.
+    #[error("bridge line had no address")]
+    NoAddr,
+    /// The address couldn't be parsed as a `SocketAddr`.
+    #[error("invalid address {0:?}")]
+    InvalidAddr(String),
+    /// A fingerprint token couldn't be parsed as a relay identity.
+    #[error("invalid relay identity {0:?}: {1}")]
+    InvalidId(String, crate::RelayIdError),
+    /// A `key=value` setting was malformed, or not a `key=value` pair.
+    #[error("invalid pluggable-transport setting {0:?}")]
+    InvalidSetting(String),
+    /// The pluggable-transport name was malformed.
+    #[error("invalid transport name {0:?}")]
+    InvalidTransport(String),
+    /// The collected fields didn't add up to a valid [`OwnedChanTarget`].
+    #[error("could not build chan target: {0}")]
+    Build(String),
+}
+
+/// Parse a torrc-style bridge line into an [`OwnedChanTarget`].
+///
+/// Accepts both the plain form (`<addr> <RSA-fingerprint> [<ed25519-id>]`)
+/// and the pluggable-transport form (`<transport> <addr> <fingerprint...>
+/// [<key>=<value>...]`). Fingerprints may be given in hex (RSA) or base64
+/// (Ed25519) form, via [`RelayId`]'s own parser.
+pub fn parse_bridge_line(line: &str) -> Result<OwnedChanTarget, BridgeLineParseError> {
+    let mut tokens = line.split_whitespace();
+    let first = tokens.next().ok_or(BridgeLineParseError::Empty)?;
+
+    // Addresses always start with a digit (IPv4) or `[` (bracketed IPv6);
+    // a transport name never does.
+    let looks_like_addr = first
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_digit() || c == '[');
+
+    let (transport, addr_tok) = if looks_like_addr {
+        (None, first)
+    } else {
+        let addr = tokens.next().ok_or(BridgeLineParseError::NoAddr)?;
+        (Some(first), addr)
+    };
+
+    let mut id_tokens = Vec::new();
+    let mut settings = Vec::new();
+    for tok in tokens {
+        if let Some((k, v)) = tok.split_once('=') {
+            settings.push((k.to_string(), v.to_string()));
+        } else {
+            id_tokens.push(tok);
+        }
+    }
+
+    let ids: RelayIds = id_tokens
+        .into_iter()
+        .map(|tok| {
+            RelayId::from_str(tok)
+                .map_err(|e| BridgeLineParseError::InvalidId(tok.to_string(), e))
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .collect();
+
+    let method = match transport {
+        Some(name) => {
+            let transport = PtTransportName::from_str(name)
+                .map_err(|_| BridgeLineParseError::InvalidTransport(name.to_string()))?;
+            let settings = PtTargetSettings::new(settings)
+                .map_err(|_| BridgeLineParseError::InvalidSetting(line.to_string()))?;
+            let addr = addr_tok
+                .parse()
+                .map_err(|_| BridgeLineParseError::InvalidAddr(addr_tok.to_string()))?;
+            ChannelMethod::Pluggable(PtTarget::new(transport, addr, settings))
+        }
+        None => {
+            let addr: SocketAddr = addr_tok
+                .parse()
+                .map_err(|_| BridgeLineParseError::InvalidAddr(addr_tok.to_string()))?;
+            ChannelMethod::Direct(vec![addr])
+        }
+    };
+
+    let direct_addrs = match &method {
+        ChannelMethod::Direct(addrs) => addrs.clone(),
+        _ => Vec::new(),
+    };
+
+    let mut builder = OwnedChanTargetBuilder::default();
+    builder.addrs(direct_addrs);
+    builder.ids(ids);
+    builder.method(method);
+    builder
+        .build()
+        .map_err(|e| BridgeLineParseError::Build(e.to_string()))
+}
+
+/// Wrap a [`ChanTarget`](crate::ChanTarget)-like value so it formats as a
+/// canonical bridge line (the inverse of [`parse_bridge_line`]).
+pub struct BridgeLineDisplay<'a, T>(pub &'a T);
+
+impl<'a, T> fmt::Display for BridgeLineDisplay<'a, T>
+where
+    T: HasChanMethod + HasRelayIds,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let method = self.0.chan_method();
+        match &method {
+            ChannelMethod::Direct(addrs) => {
+                if let Some(addr) = addrs.first() {
+                    write!(f, "{addr}")?;
+                }
+            }
+            ChannelMethod::Pluggable(pt) => {
+                write!(f, "{} {}", pt.transport(), pt.addr())?;
+            }
+            _ => {}
+        }
+        if let Some(id) = self.0.identity(RelayIdType::Rsa) {
+            write!(f, " {id}")?;
+        }
+        if let Some(id) = self.0.identity(RelayIdType::Ed25519) {
+            write!(f, " {id}")?;
+        }
+        // Transport settings trail the fingerprints, as in the module-level example.
+        if let ChannelMethod::Pluggable(pt) = &method {
+            for (k, v) in pt.settings().iter() {
+                write!(f, " {k}={v}")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 #![cfg_attr(docsrs, feature(doc_auto_cfg, doc_cfg))]
 #![doc = include_str!("../README.md")]
 
+mod bridge_desc;
 #[cfg(feature = "decode")]
 pub mod decode;
 #[macro_use]
@@ -12,6 +13,7 @@ mod transport;
 #[cfg(feature = "verbatim")]
 pub mod verbatim;
 
+pub use bridge_desc::{parse_bridge_line, BridgeLineDisplay, BridgeLineParseError};
 pub use ids::{
     by_id::{ByRelayIds, ByRelayIdsError, ListByRelayIds, ListByRelayIdsError, ListByRelayIdsIter},
     set::RelayIdSet,
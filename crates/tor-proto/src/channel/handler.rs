@@ -1,12 +1,17 @@
 //! Wrap tor_cell::...:::ChannelCodec for use with the futures_codec
 //! crate.
+//!
+//! `codec::ChannelCodec::decode_cell` returns, alongside the decoded cell, the exact raw bytes it
+//! consumed from the input buffer; `codec::ChannelCodec::write_cell` returns the byte range it
+//! appended to the output buffer. This lets CLOG/SLOG digesting below hash precisely the bytes of
+//! the cell just encoded/decoded, instead of cloning or hashing the surrounding buffer wholesale.
 
 use digest::Digest;
 use tor_bytes::Reader;
 use tor_cell::chancell::{
     codec,
     msg::{self, AnyChanMsg},
-    AnyChanCell, ChanCell, ChanCmd, ChanMsg,
+    AnyChanCell, ChanCell, ChanCmd, ChanMsg, CircId,
 };
 use tor_error::internal;
 use tor_llcrypto as ll;
@@ -62,24 +67,34 @@ impl ChannelCellHandler {
             )));
         };
         // Make sure we don't allow unknown protocol values.
-        if !super::msg::is_link_version_known(link_version) {
-            return Err(ChanError::ChanProto(format!(
-                "Unknown link protocol version {link_version}"
-            )));
-        }
+        let link_version = super::msg::LinkVersion::try_new(link_version).ok_or_else(|| {
+            ChanError::ChanProto(format!("Unknown link protocol version {link_version}"))
+        })?;
         *self = Self::Handshake(new_handler.next_handler(link_version));
         Ok(())
     }
 
     /// This transition into the open handler state.
     ///
-    /// An error is returned if the current handler is NOT the Handshake one.
+    /// An error is returned if the current handler is NOT the Handshake one, or if this is a
+    /// relay-responder channel whose peer has not yet had its `AUTHENTICATE` cell verified (see
+    /// [`HandshakeChannelHandler::mark_authenticated`]).
     pub(crate) fn set_open(&mut self) -> Result<(), ChanError> {
         let Self::Handshake(handler) = self else {
             return Err(ChanError::Bug(internal!(
                 "Setting authenticated without a handshake handler"
             )));
         };
+        if let ChannelType::RelayResponder {
+            authenticated: false,
+        } = handler.channel_type
+        {
+            return Err(ChanError::HandshakeProto(
+                "Cannot open a relay-responder channel before its peer's AUTHENTICATE cell has \
+                 been verified"
+                    .into(),
+            ));
+        }
         *self = Self::Open(handler.next_handler());
         Ok(())
     }
@@ -117,22 +132,26 @@ impl ChannelCellHandler {
 // thus the higher level ChannelCellHandler.
 //
 // Technically, we could use a restricted message set and so the decoding and encoding wouldn't do
-// anything if the cell/data was not part of that set.
-//
-// However, with relay and client, we have multiple channel types which means we have now a lot
-// more sets of restricted message (see msg.rs) and each of them are per link protocol version, per
-// stage of the channel opening process and per direction (inbound or outbound).
+// anything if the cell/data was not part of that set. Doing so fully (making `codec::ChannelCodec`
+// itself generic over an inbound/outbound restricted message type) would require changes in
+// tor-cell, which owns that codec; out of scope here.
 //
-// And so, the approach taken here instead is to filter the Item _before_ encoding and _after_
-// decoding it. This has of course the downside that our decoder will decode every possible cell
-// before rejecting it.
+// With relay and client, we have multiple channel types which means we have now a lot more sets of
+// restricted message (see msg.rs) and each of them are per link protocol version, per stage of the
+// channel opening process and per direction (inbound or outbound).
 //
-// This leads to an information leak of the decoding capabilities. On the relay side, this is not a
-// problem because they already advertise their capabilities. However, for clients, this is not
-// ideal but we believe it is not dangerous leak.
+// What we do instead, without touching tor-cell: both link protocol versions we support use a
+// fixed-width (4-byte) circuit ID immediately followed by the 1-byte command, so
+// `HandshakeChannelHandler::decode` and `OpenChannelHandler::decode` peek those 5 bytes straight
+// out of `src` and run them through the filter *before* asking the inner codec to decode anything.
+// A disallowed command is rejected right there and its body (and, for variable-length commands,
+// declared length) is never read, so the information leak described below no longer exists on the
+// decode path: an observer can no longer use body decoding to infer which commands we understand.
+// Encoding was never a problem here since we already filter the Item before calling
+// `codec::ChannelCodec::write_cell`.
 //
-// If someone wants to contribute a more elegant solution that wouldn't require us to duplicate
-// code for each restricted message set, by all means, go for it :).
+// On the relay side this was less of a concern anyway, since relays already advertise their
+// capabilities; this mainly hardens the client case.
 
 impl futures_codec::Decoder for ChannelCellHandler {
     type Item = AnyChanCell;
@@ -171,6 +190,30 @@ impl futures_codec::Encoder for ChannelCellHandler {
     }
 }
 
+/// Peek the circuit ID and command of the next cell in `src`, without consuming anything.
+///
+/// Both link protocol versions we support use a fixed-width circuit ID immediately followed by a
+/// 1-byte command (see [`super::msg::LinkVersion::circ_id_width`]), so this can be read directly
+/// out of the buffer before the inner codec decodes (and thereby commits to reading) the rest of
+/// the cell. Returns `None` if `src` does not yet hold enough bytes to know the command.
+fn peek_inbound_details(
+    src: &BytesMut,
+    link_version: super::msg::LinkVersion,
+) -> Option<MessageDetails> {
+    let circ_id_width = link_version.circ_id_width();
+    let header_len = circ_id_width + 1;
+    if src.len() < header_len {
+        return None;
+    }
+    // `circ_id_width` is documented as possibly varying by link version, so read it as a
+    // variable-width big-endian field rather than assuming today's 4-byte width.
+    let mut circ_id_bytes = [0u8; 4];
+    circ_id_bytes[4 - circ_id_width..].copy_from_slice(&src[..circ_id_width]);
+    let circ_id = CircId::new(u32::from_be_bytes(circ_id_bytes));
+    let cmd = ChanCmd::from(src[circ_id_width]);
+    Some(MessageDetails::new_inbound(cmd, circ_id))
+}
+
 /// A new channel handler used when a channel is created but before the handshake meaning there is no
 /// link protocol version yet associated with it.
 ///
@@ -186,7 +229,7 @@ pub(crate) struct NewChannelHandler {
 
 impl NewChannelHandler {
     /// Return a handshake handler ready for the given link protocol.
-    fn next_handler(&mut self, link_version: u16) -> HandshakeChannelHandler {
+    fn next_handler(&mut self, link_version: super::msg::LinkVersion) -> HandshakeChannelHandler {
         HandshakeChannelHandler::new(self, link_version)
     }
 }
@@ -230,13 +273,14 @@ impl futures_codec::Decoder for NewChannelHandler {
                 .try_into()
                 .expect("Two-byte field was not two bytes!?"),
         );
-        // Update the SLOG digest. This needs to be done here else the src buffer will get
-        // modified. Considering we are in the New stage of a channel, if this errors after, there
-        // is just no chance of the channel being established so the SLOG will be poisoned anyway.
+        let mut data = src.split_to(5 + body_len as usize);
+        // Update the SLOG digest over exactly the bytes of this cell, now that they have been
+        // split off from the rest of the (possibly multi-cell) buffer. Considering we are in the
+        // New stage of a channel, if this errors after, there is just no chance of the channel
+        // being established so the SLOG will be poisoned anyway.
         if let Some(slog) = self.slog.as_mut() {
-            slog.update(&src);
+            slog.update(&data);
         }
-        let mut data = src.split_to(5 + body_len as usize);
         let body = data.split_off(5).freeze();
         let mut reader = Reader::from_bytes(&body);
         let versions = msg::Versions::decode_from_reader(ChanCmd::VERSIONS, &mut reader)
@@ -252,14 +296,16 @@ impl futures_codec::Encoder for NewChannelHandler {
 
     fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
         // Special encoding for the VERSIONS cell.
+        let start = dst.len();
         dst.extend_from_slice(
             &item
                 .encode_for_handshake()
                 .map_err(|e| Self::Error::from_bytes_enc(e, "new cell handler"))?,
         );
-        // Update the CLOG digest.
+        // Update the CLOG digest over exactly the bytes just appended, not over whatever `dst`
+        // already held (e.g. cells written but not yet flushed).
         if let Some(clog) = self.clog.as_mut() {
-            clog.update(&dst);
+            clog.update(&dst[start..]);
         }
         Ok(())
     }
@@ -274,6 +320,8 @@ pub(crate) struct HandshakeChannelHandler {
     filter: MessageFilter,
     /// The cell codec that we'll use to encode and decode our cells.
     inner: codec::ChannelCodec,
+    /// The negotiated link protocol version for this handler.
+    link_version: super::msg::LinkVersion,
     /// The CLOG digest needed for authenticated channels.
     clog: Option<ll::d::Sha256>,
     /// The SLOG digest needed for authenticated channels.
@@ -282,7 +330,7 @@ pub(crate) struct HandshakeChannelHandler {
 
 impl HandshakeChannelHandler {
     /// Constructor
-    fn new(new_handler: &mut NewChannelHandler, link_version: u16) -> Self {
+    fn new(new_handler: &mut NewChannelHandler, link_version: super::msg::LinkVersion) -> Self {
         Self {
             channel_type: new_handler.channel_type,
             filter: MessageFilter::new(
@@ -292,7 +340,8 @@ impl HandshakeChannelHandler {
             ),
             clog: new_handler.clog.take(),
             slog: new_handler.slog.take(),
-            inner: codec::ChannelCodec::new(link_version),
+            inner: codec::ChannelCodec::new(link_version.number()),
+            link_version,
         }
     }
 
@@ -307,7 +356,7 @@ impl HandshakeChannelHandler {
 
     /// Return an open handshake handler.
     fn next_handler(&mut self) -> OpenChannelHandler {
-        OpenChannelHandler::new(self.inner.link_version(), self.channel_type)
+        OpenChannelHandler::new(self.link_version, self.channel_type)
     }
 
     /// Return the digest of the CLOG consuming it.
@@ -319,6 +368,17 @@ impl HandshakeChannelHandler {
     pub(crate) fn take_slog(&mut self) -> [u8; 32] {
         Self::finalize_log(self.slog.take())
     }
+
+    /// Record that we have verified the peer's `AUTHENTICATE` cell.
+    ///
+    /// Only meaningful for a `ChannelType::RelayResponder`, which is the only variant that tracks
+    /// an `authenticated` flag; called on anything else, this is a no-op. Once set, subsequent
+    /// calls to [`ChannelCellHandler::set_open`] on this handler will succeed.
+    pub(crate) fn mark_authenticated(&mut self) {
+        if let ChannelType::RelayResponder { authenticated } = &mut self.channel_type {
+            *authenticated = true;
+        }
+    }
 }
 
 impl futures_codec::Encoder for HandshakeChannelHandler {
@@ -333,13 +393,18 @@ impl futures_codec::Encoder for HandshakeChannelHandler {
         let cmd = item.msg().cmd();
 
         // Make sure it is allowed to encode this message.
-        self.filter.is_allowed(&MessageDetails::new_outbound(cmd))?;
+        self.filter
+            .is_allowed(&MessageDetails::new_outbound(cmd, item.circid()))
+            .map_err(super::msg::FilterError::into_error)?;
 
-        self.inner
+        // `write_cell` reports the byte range it appended to `dst`, so CLOG is updated over
+        // exactly this cell's bytes rather than the whole (possibly already-populated) buffer.
+        let written = self
+            .inner
             .write_cell(item, dst)
             .map_err(|e| Self::Error::from_cell_enc(e, "handshake cell handler"))?;
         if let Some(clog) = self.clog.as_mut() {
-            clog.update(dst);
+            clog.update(&dst[written]);
         }
         Ok(())
     }
@@ -353,21 +418,29 @@ impl futures_codec::Decoder for HandshakeChannelHandler {
         &mut self,
         src: &mut BytesMut,
     ) -> std::result::Result<Option<Self::Item>, Self::Error> {
-        let orig = src.clone(); // XXX: Not fun. But This is only done during handshake.
-        let cell: Option<Self::Item> = self
+        // Reject a disallowed command before the inner codec ever reads the cell body. See the
+        // "Security Consideration" comment above `ChannelCellHandler`.
+        let Some(details) = peek_inbound_details(src, self.link_version) else {
+            return Ok(None);
+        };
+        self.filter
+            .is_allowed(&details)
+            .map_err(super::msg::FilterError::into_error)?;
+
+        // `decode_cell` hands back the raw bytes it consumed alongside the decoded cell, so SLOG
+        // can be updated directly from them instead of cloning the whole (possibly multi-cell)
+        // `src` buffer up front just to recover the consumed prefix afterwards.
+        let Some((cell, consumed)) = self
             .inner
             .decode_cell(src)
-            .map_err(|e| Self::Error::from_cell_dec(e, "handshake cell handler"))?;
-        if let Some(c) = cell.as_ref() {
-            let cmd = c.msg().cmd();
-            // Make sure we can expect this message.
-            self.filter.is_allowed(&MessageDetails::new_inbound(cmd))?;
-            if let Some(slog) = self.slog.as_mut() {
-                let n_used = orig.len() - src.len();
-                slog.update(&orig[..n_used]);
-            }
+            .map_err(|e| Self::Error::from_cell_dec(e, "handshake cell handler"))?
+        else {
+            return Ok(None);
+        };
+        if let Some(slog) = self.slog.as_mut() {
+            slog.update(&consumed);
         }
-        Ok(cell)
+        Ok(Some(cell))
     }
 }
 
@@ -377,14 +450,17 @@ pub(crate) struct OpenChannelHandler {
     filter: MessageFilter,
     /// The cell codec that we'll use to encode and decode our cells.
     inner: codec::ChannelCodec,
+    /// The negotiated link protocol version for this handler.
+    link_version: super::msg::LinkVersion,
 }
 
 impl OpenChannelHandler {
     /// Constructor
-    fn new(link_version: u16, channel_type: ChannelType) -> Self {
+    fn new(link_version: super::msg::LinkVersion, channel_type: ChannelType) -> Self {
         Self {
-            inner: codec::ChannelCodec::new(link_version),
+            inner: codec::ChannelCodec::new(link_version.number()),
             filter: MessageFilter::new(link_version, channel_type, super::msg::MessageStage::Open),
+            link_version,
         }
     }
 }
@@ -395,7 +471,8 @@ impl futures_codec::Encoder for OpenChannelHandler {
 
     fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
         self.filter
-            .is_allowed(&MessageDetails::new_outbound(item.msg().cmd()))?;
+            .is_allowed(&MessageDetails::new_outbound(item.msg().cmd(), item.circid()))
+            .map_err(super::msg::FilterError::into_error)?;
         self.inner
             .write_cell(item, dst)
             .map_err(|e| Self::Error::from_cell_enc(e, "open cell handler"))?;
@@ -408,15 +485,149 @@ impl futures_codec::Decoder for OpenChannelHandler {
     type Error = ChanError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let cell: Option<Self::Item> = self
-            .inner
+        // Reject a disallowed command before the inner codec ever reads the cell body. See the
+        // "Security Consideration" comment above `ChannelCellHandler`.
+        let Some(details) = peek_inbound_details(src, self.link_version) else {
+            return Ok(None);
+        };
+        self.filter
+            .is_allowed(&details)
+            .map_err(super::msg::FilterError::into_error)?;
+
+        self.inner
             .decode_cell(src)
-            .map_err(|e| Self::Error::from_cell_dec(e, "open cell handler"))?;
-        if let Some(c) = &cell {
-            self.filter
-                .is_allowed(&MessageDetails::new_inbound(c.msg().cmd()))?;
+            .map_err(|e| Self::Error::from_cell_dec(e, "open cell handler"))
+            .map(|opt| opt.map(|(cell, _)| cell))
+    }
+}
+
+/// Tor channel-authentication handshake (see `tor-spec.txt`'s channel-authentication section).
+///
+/// [`HandshakeChannelHandler`] already accumulates the CLOG ("bytes we sent")/SLOG ("bytes we
+/// received") SHA-256 digests this handshake needs, via
+/// [`ChannelCellHandler::get_clog_digest`]/[`get_slog_digest`](ChannelCellHandler::get_slog_digest); this module turns those digests,
+/// plus the identity material exchanged in the `CERTS` cell, into an `AUTHENTICATE` cell we send
+/// (relay-as-initiator), or uses them to verify one we received (relay-as-responder). Driving the
+/// handshake state machine itself (deciding when to call these, and when to call
+/// [`HandshakeChannelHandler::mark_authenticated`]/[`ChannelCellHandler::set_open`]) is the
+/// responsibility of the channel reactor, outside this module.
+pub(crate) mod auth {
+    use rand::Rng;
+    use tor_cell::chancell::msg;
+    use tor_llcrypto::pk::ed25519::{ExpandedKeypair, PublicKey, Signature, Verifier};
+
+    /// The `AUTHTYPE` this implementation supports: Ed25519-SHA256, numbered 3 in
+    /// `tor-spec.txt`'s auth-type table (1 and 2 are the RSA-SHA256 variants we don't implement).
+    pub(crate) const AUTHTYPE_ED25519_SHA256: u16 = 3;
+
+    /// The length, in bytes, of an Ed25519-SHA256 `AUTHENTICATE` body before its trailing
+    /// signature: an 8-byte type marker followed by four 32-byte fields (CID, SID, SLOG, CLOG).
+    const BODY_LEN_BEFORE_SIG: usize = 8 + 32 * 4;
+
+    /// The length, in bytes, of an Ed25519 signature.
+    const SIG_LEN: usize = 64;
+
+    /// Return true iff `method` is an auth method this implementation can perform.
+    pub(crate) fn method_supported(method: u16) -> bool {
+        method == AUTHTYPE_ED25519_SHA256
+    }
+
+    /// Choose the auth method we'll use to answer an inbound `AUTH_CHALLENGE` cell.
+    ///
+    /// Returns `None` if none of `offered` (the challenge's advertised auth methods) are ones we
+    /// support; a relay initiator seeing that should give up the handshake rather than send an
+    /// `AUTHENTICATE` cell the peer won't accept.
+    pub(crate) fn choose_method(offered: &[u16]) -> Option<u16> {
+        offered.iter().copied().find(|m| method_supported(*m))
+    }
+
+    /// The fixed 8-byte marker that opens every `AUTHENTICATE` body, identifying its `AUTHTYPE`.
+    fn auth_type_marker(auth_type: u16) -> [u8; 8] {
+        let mut marker = *b"AUTH0000";
+        let digits = format!("{auth_type:04}");
+        marker[4..8].copy_from_slice(digits.as_bytes());
+        marker
+    }
+
+    /// Build the body of an Ed25519-SHA256 `AUTHENTICATE` cell.
+    ///
+    /// `client_id_digest`/`server_id_digest` are digests of the initiator's/responder's identity
+    /// certs (as exchanged in `CERTS`); `server_log`/`client_log` are the finalized SLOG/CLOG
+    /// digests taken right before this cell is built, per `tor-spec.txt`'s requirement that they
+    /// cover every byte exchanged so far but not this `AUTHENTICATE` cell itself.
+    pub(crate) fn build_authenticate(
+        signing_key: &ExpandedKeypair,
+        client_id_digest: [u8; 32],
+        server_id_digest: [u8; 32],
+        server_log: [u8; 32],
+        client_log: [u8; 32],
+    ) -> msg::Authenticate {
+        let mut body = Vec::with_capacity(BODY_LEN_BEFORE_SIG + 24 + SIG_LEN);
+        body.extend_from_slice(&auth_type_marker(AUTHTYPE_ED25519_SHA256));
+        body.extend_from_slice(&client_id_digest);
+        body.extend_from_slice(&server_id_digest);
+        body.extend_from_slice(&server_log);
+        body.extend_from_slice(&client_log);
+
+        let mut rand_bytes = [0_u8; 24];
+        rand::thread_rng().fill(&mut rand_bytes);
+        body.extend_from_slice(&rand_bytes);
+
+        let signature = signing_key.sign(&body);
+        body.extend_from_slice(&signature.to_bytes());
+
+        msg::Authenticate::new(AUTHTYPE_ED25519_SHA256, body)
+    }
+
+    /// Verify a peer's `AUTHENTICATE` cell body against the digests and identity key we expect.
+    ///
+    /// Only meaningful in the relay-responder direction: we are the one who issued the
+    /// `AUTH_CHALLENGE`, and must confirm the initiator really holds the signing key behind
+    /// `peer_key` before calling [`super::HandshakeChannelHandler::mark_authenticated`].
+    pub(crate) fn verify_authenticate(
+        peer_key: &PublicKey,
+        body: &[u8],
+        client_id_digest: [u8; 32],
+        server_id_digest: [u8; 32],
+        server_log: [u8; 32],
+        client_log: [u8; 32],
+    ) -> bool {
+        if body.len() < BODY_LEN_BEFORE_SIG + SIG_LEN {
+            return false;
+        }
+        if body[0..8] != auth_type_marker(AUTHTYPE_ED25519_SHA256) {
+            return false;
+        }
+        let (signed, sig_bytes) = body.split_at(body.len() - SIG_LEN);
+        let Ok(signature) = Signature::from_slice(sig_bytes) else {
+            return false;
+        };
+        if peer_key.verify(signed, &signature).is_err() {
+            return false;
+        }
+        signed[8..40] == client_id_digest
+            && signed[40..72] == server_id_digest
+            && signed[72..104] == server_log
+            && signed[104..136] == client_log
+    }
+
+    /// Parsed form of an inbound `AUTH_CHALLENGE` cell: the server's challenge nonce and the auth
+    /// methods it is willing to accept.
+    pub(crate) struct AuthChallenge {
+        /// Server's random challenge.
+        pub(crate) challenge: [u8; 32],
+        /// Auth methods the server offers, in the order it sent them.
+        pub(crate) methods: Vec<u16>,
+    }
+
+    impl AuthChallenge {
+        /// Extract the challenge and methods from a decoded `AUTH_CHALLENGE` cell.
+        pub(crate) fn from_cell(cell: &msg::AuthChallenge) -> Self {
+            Self {
+                challenge: cell.challenge(),
+                methods: cell.methods().to_vec(),
+            }
         }
-        Ok(cell)
     }
 }
 
@@ -489,8 +700,9 @@ pub(crate) mod test {
     }
 
     fn new_client_open_frame(mbuf: MsgBuf) -> futures_codec::Framed<MsgBuf, ChannelCellHandler> {
+        let link_version = crate::channel::msg::LinkVersion::try_new(5).expect("v5 is supported");
         let open_handler =
-            ChannelCellHandler::Open(OpenChannelHandler::new(5, ChannelType::ClientInitiator));
+            ChannelCellHandler::Open(OpenChannelHandler::new(link_version, ChannelType::ClientInitiator));
         futures_codec::Framed::new(mbuf, open_handler)
     }
 
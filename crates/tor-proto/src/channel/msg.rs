@@ -6,7 +6,7 @@
 //! This module also defines [`MessageFilter`] which can be used to filter messages based on
 //! specific details of the message such as direction, command, channel type and channel stage.
 
-use tor_cell::chancell::ChanCmd;
+use tor_cell::chancell::{msg::AnyChanMsg, ChanCmd, ChanMsg as _, CircId};
 
 use crate::{channel::ChannelType, Error};
 
@@ -93,60 +93,31 @@ pub(crate) mod linkv4 {
         }
     }
 
-    /// Return true iff the given channel type at the given channel negotiation stage for the given
-    /// message details is allowed.
-    ///
-    /// In order to learn the answer, we check against the specific restricted message set if the
-    /// command is known and if so, it is allowed.
-    ///
-    /// This is very verbose and testing every possible branch. It is more important that it is
-    /// easily readable by a human as in easy to follow than to be compact. A lot can go wrong
-    /// if this is confusing.
-    ///
-    /// XXX: Very code duplicated with the linkv5 is_allowed() function so any improvements not
-    /// compromising readability is very welcome.
-    pub(crate) fn is_allowed(
-        chan_type: ChannelType,
-        stage: &MessageStage,
-        details: &MessageDetails,
-    ) -> bool {
-        let cmd = details.cmd;
-        match chan_type {
-            ChannelType::ClientInitiator => match stage {
-                MessageStage::Handshake => match details.direction {
-                    MessageDirection::Inbound => HandshakeRelayResponderMsg::is_known_cmd(cmd),
-                    MessageDirection::Outbound => HandshakeClientInitiatorMsg::is_known_cmd(cmd),
-                },
-                MessageStage::Open => match details.direction {
-                    MessageDirection::Inbound => OpenChanMsgR2C::is_known_cmd(cmd),
-                    MessageDirection::Outbound => OpenChanMsgC2R::is_known_cmd(cmd),
-                },
-            },
-            ChannelType::RelayInitiator => match stage {
-                MessageStage::Handshake => match details.direction {
-                    MessageDirection::Inbound => HandshakeRelayResponderMsg::is_known_cmd(cmd),
-                    MessageDirection::Outbound => HandshakeRelayInitiatorMsg::is_known_cmd(cmd),
-                },
-                // Regardless of Inbound or Outbound, same restricted set for Relay <-> Relay.
-                MessageStage::Open => OpenChanMsgR2R::is_known_cmd(cmd),
-            },
-            ChannelType::RelayResponder { authenticated } => match stage {
-                // Authenticated is only learned after the handshake is done.
-                MessageStage::Handshake => match details.direction {
-                    MessageDirection::Inbound => HandshakeRelayInitiatorMsg::is_known_cmd(cmd),
-                    MessageDirection::Outbound => HandshakeRelayResponderMsg::is_known_cmd(cmd),
-                },
-                MessageStage::Open => match authenticated {
-                    // Unauthenticated channel means, as a Relay, we respond to a Client.
-                    false => match details.direction {
-                        MessageDirection::Inbound => OpenChanMsgC2R::is_known_cmd(cmd),
-                        MessageDirection::Outbound => OpenChanMsgR2C::is_known_cmd(cmd),
-                    },
-                    // Authenticated channel means, as a Relay, we respond to a Relay. Regardless
-                    // of Inbound or Outbound, same restricted set for Relay <-> Relay.
-                    true => OpenChanMsgR2R::is_known_cmd(cmd),
-                },
-            },
+    /// Marker type for this link protocol version, used to dispatch to its restricted sets
+    /// through [`LinkProtocol`](super::LinkProtocol).
+    pub(crate) struct LinkV4;
+
+    impl super::LinkProtocol for LinkV4 {
+        fn handshake_relay_initiator_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            HandshakeRelayInitiatorMsg::is_known_cmd(cmd)
+        }
+        fn handshake_relay_responder_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            HandshakeRelayResponderMsg::is_known_cmd(cmd)
+        }
+        fn handshake_client_initiator_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            HandshakeClientInitiatorMsg::is_known_cmd(cmd)
+        }
+        fn open_c2r_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            OpenChanMsgC2R::is_known_cmd(cmd)
+        }
+        fn open_r2c_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            OpenChanMsgR2C::is_known_cmd(cmd)
+        }
+        fn open_r2r_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            OpenChanMsgR2R::is_known_cmd(cmd)
+        }
+        fn version_number(&self) -> u16 {
+            4
         }
     }
 }
@@ -239,61 +210,366 @@ pub(crate) mod linkv5 {
         }
     }
 
-    /// Return true iff the given channel type at the given channel negotiation stage for the given
-    /// message details is allowed.
+    /// Marker type for this link protocol version, used to dispatch to its restricted sets
+    /// through [`LinkProtocol`](super::LinkProtocol).
+    pub(crate) struct LinkV5;
+
+    impl super::LinkProtocol for LinkV5 {
+        fn handshake_relay_initiator_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            HandshakeRelayInitiatorMsg::is_known_cmd(cmd)
+        }
+        fn handshake_relay_responder_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            HandshakeRelayResponderMsg::is_known_cmd(cmd)
+        }
+        fn handshake_client_initiator_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            HandshakeClientInitiatorMsg::is_known_cmd(cmd)
+        }
+        fn open_c2r_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            OpenChanMsgC2R::is_known_cmd(cmd)
+        }
+        fn open_r2c_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            OpenChanMsgR2C::is_known_cmd(cmd)
+        }
+        fn open_r2r_known(&self, cmd: tor_cell::chancell::ChanCmd) -> bool {
+            OpenChanMsgR2R::is_known_cmd(cmd)
+        }
+        fn version_number(&self) -> u16 {
+            5
+        }
+    }
+}
+
+/// A link protocol version's view of which messages are allowed where.
+///
+/// Implementations supply only the per-version restricted message sets (through the six
+/// `*_known` methods) and their [`version_number`](LinkProtocol::version_number); the dispatch
+/// logic that decides, from a [`ChannelType`], [`MessageStage`] and [`MessageDirection`], which
+/// set applies lives once, in the default method bodies here. Adding a future link version is
+/// then just "define the restricted enums + implement the six `*_known` methods and
+/// `version_number`", rather than copying the whole dispatch tree.
+pub(crate) trait LinkProtocol {
+    /// Return true iff `cmd` is a handshake message a Relay-as-Initiator may send/receive.
+    fn handshake_relay_initiator_known(&self, cmd: ChanCmd) -> bool;
+    /// Return true iff `cmd` is a handshake message a Relay-as-Responder may send/receive.
+    fn handshake_relay_responder_known(&self, cmd: ChanCmd) -> bool;
+    /// Return true iff `cmd` is a handshake message a Client-as-Initiator may send/receive.
+    fn handshake_client_initiator_known(&self, cmd: ChanCmd) -> bool;
+    /// Return true iff `cmd` is allowed Client-to-Relay on an open channel.
+    fn open_c2r_known(&self, cmd: ChanCmd) -> bool;
+    /// Return true iff `cmd` is allowed Relay-to-Client on an open channel.
+    fn open_r2c_known(&self, cmd: ChanCmd) -> bool;
+    /// Return true iff `cmd` is allowed Relay-to-Relay on an open channel.
+    fn open_r2r_known(&self, cmd: ChanCmd) -> bool;
+
+    /// The link protocol version number this implementation corresponds to, e.g. `4` or `5`.
+    fn version_number(&self) -> u16;
+
+    /// Return true iff `cmd`, traveling in `direction`, is allowed during the handshake on a
+    /// channel of type `chan_type`.
+    fn handshake_allowed(
+        &self,
+        chan_type: ChannelType,
+        direction: &MessageDirection,
+        cmd: ChanCmd,
+    ) -> bool {
+        match chan_type {
+            ChannelType::ClientInitiator => match direction {
+                MessageDirection::Inbound => self.handshake_relay_responder_known(cmd),
+                MessageDirection::Outbound => self.handshake_client_initiator_known(cmd),
+            },
+            ChannelType::RelayInitiator => match direction {
+                MessageDirection::Inbound => self.handshake_relay_responder_known(cmd),
+                MessageDirection::Outbound => self.handshake_relay_initiator_known(cmd),
+            },
+            // Authenticated is only learned after the handshake is done, so it plays no role here.
+            ChannelType::RelayResponder { .. } => match direction {
+                MessageDirection::Inbound => self.handshake_relay_initiator_known(cmd),
+                MessageDirection::Outbound => self.handshake_relay_responder_known(cmd),
+            },
+        }
+    }
+
+    /// Return true iff `cmd`, traveling in `direction`, is allowed on an open channel of type
+    /// `chan_type`. `authenticated` is only meaningful for a `RelayResponder`.
+    fn open_allowed(
+        &self,
+        chan_type: ChannelType,
+        authenticated: bool,
+        direction: &MessageDirection,
+        cmd: ChanCmd,
+    ) -> bool {
+        match chan_type {
+            ChannelType::ClientInitiator => match direction {
+                MessageDirection::Inbound => self.open_r2c_known(cmd),
+                MessageDirection::Outbound => self.open_c2r_known(cmd),
+            },
+            // Regardless of Inbound or Outbound, same restricted set for Relay <-> Relay.
+            ChannelType::RelayInitiator => self.open_r2r_known(cmd),
+            ChannelType::RelayResponder { .. } => match authenticated {
+                // Unauthenticated channel means, as a Relay, we respond to a Client.
+                false => match direction {
+                    MessageDirection::Inbound => self.open_c2r_known(cmd),
+                    MessageDirection::Outbound => self.open_r2c_known(cmd),
+                },
+                // Authenticated channel means, as a Relay, we respond to a Relay. Regardless of
+                // Inbound or Outbound, same restricted set for Relay <-> Relay.
+                true => self.open_r2r_known(cmd),
+            },
+        }
+    }
+
+    /// Return true iff the given channel type at the given channel negotiation stage for the
+    /// given message details is allowed.
     ///
-    /// In order to learn the answer, we check against the specific restricted message set if the
-    /// command is known and if so, it is allowed.
+    /// This is the single dispatch point shared by every link protocol version: it picks
+    /// [`handshake_allowed`](Self::handshake_allowed) or [`open_allowed`](Self::open_allowed)
+    /// based on `stage`, and derives `authenticated` from `chan_type` for the latter.
+    fn is_allowed(&self, chan_type: ChannelType, stage: &MessageStage, details: &MessageDetails) -> bool {
+        let cmd = details.cmd;
+        match stage {
+            MessageStage::Handshake => self.handshake_allowed(chan_type, &details.direction, cmd),
+            MessageStage::Open => {
+                let authenticated =
+                    matches!(chan_type, ChannelType::RelayResponder { authenticated: true });
+                self.open_allowed(chan_type, authenticated, &details.direction, cmd)
+            }
+        }
+    }
+
+    /// Return true iff `cmd` appears in *any* restricted set for this link version, regardless of
+    /// channel type, stage or direction.
     ///
-    /// This is very verbose and testing every possible branch. It is more important that it is
-    /// easily readable by a human as in easy to follow than to be compact. A lot can go wrong
-    /// if this is confusing.
+    /// Only used to tell [`RejectReason::UnknownCommand`] apart from
+    /// [`RejectReason::DisallowedHere`] once [`is_allowed`](Self::is_allowed) has already said no.
+    fn is_command_known(&self, cmd: ChanCmd) -> bool {
+        self.handshake_relay_initiator_known(cmd)
+            || self.handshake_relay_responder_known(cmd)
+            || self.handshake_client_initiator_known(cmd)
+            || self.open_c2r_known(cmd)
+            || self.open_r2c_known(cmd)
+            || self.open_r2r_known(cmd)
+    }
+
+    /// Confirm that `details` is allowed for `chan_type` at `stage`, returning a structured
+    /// [`FilterError`] describing the rejection otherwise.
     ///
-    /// XXX: Very code duplicated with the linkv4 is_allowed() function so any improvements not
-    /// compromising readability is very welcome.
-    pub(crate) fn is_allowed(
+    /// Besides the command allow-list, this also enforces that `details.circ_id` is legal for
+    /// `details.cmd` (e.g. `NETINFO` must carry circ-id 0, `DESTROY` must not): a command can be
+    /// allowed in this context and still be a protocol violation if it arrives on the wrong
+    /// circuit ID.
+    fn check(
+        &self,
         chan_type: ChannelType,
         stage: &MessageStage,
         details: &MessageDetails,
-    ) -> bool {
-        let cmd = details.cmd;
-        match chan_type {
-            ChannelType::ClientInitiator => match stage {
-                MessageStage::Handshake => match details.direction {
-                    MessageDirection::Inbound => HandshakeRelayResponderMsg::is_known_cmd(cmd),
-                    MessageDirection::Outbound => HandshakeClientInitiatorMsg::is_known_cmd(cmd),
+    ) -> Result<(), FilterError> {
+        if self.is_allowed(chan_type, stage, details) {
+            if details.cmd.accepts_circid_val(details.circ_id) {
+                return Ok(());
+            }
+            return Err(FilterError {
+                reason: RejectReason::IllegalCircId,
+                cmd: details.cmd,
+                direction: details.direction,
+                stage: *stage,
+                channel_type: chan_type,
+                link_version: self.version_number(),
+                circ_id: details.circ_id,
+            });
+        }
+        let reason = if self.is_command_known(details.cmd) {
+            RejectReason::DisallowedHere
+        } else {
+            RejectReason::UnknownCommand
+        };
+        Err(FilterError {
+            reason,
+            cmd: details.cmd,
+            direction: details.direction,
+            stage: *stage,
+            channel_type: chan_type,
+            link_version: self.version_number(),
+            circ_id: details.circ_id,
+        })
+    }
+
+    /// Confirm that `msg` is allowed for `chan_type`/`stage`/`details`, then consume it and narrow
+    /// it into the matching [`Accepted`] variant.
+    ///
+    /// `details` must describe `msg` (same command, same direction); this is only ever called
+    /// from [`MessageFilter::decode`], which builds `details` from `msg` itself.
+    fn decode(
+        &self,
+        chan_type: ChannelType,
+        stage: &MessageStage,
+        details: &MessageDetails,
+        msg: AnyChanMsg,
+    ) -> Result<Accepted, Error> {
+        self.check(chan_type, stage, details)
+            .map_err(FilterError::into_error)?;
+        Accepted::narrow(chan_type, stage, &details.direction, msg).map_err(|_| {
+            stage.to_err(format!(
+                "Internal error: cell passed the filter but failed to decode for {details}"
+            ))
+        })
+    }
+}
+
+/// Why a [`MessageFilter`] rejected a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RejectReason {
+    /// The command does not appear in any restricted set we know of for this link version: it is
+    /// not merely out of place, it is not a command this version ever allows anywhere.
+    UnknownCommand,
+    /// The command is recognized for this link version, but not in this channel type/stage/
+    /// direction.
+    DisallowedHere,
+    /// The command is allowed here, but arrived on a circuit ID that is illegal for it (e.g. a
+    /// non-zero circ-id on a `NETINFO`, or a zero circ-id on a `DESTROY`).
+    IllegalCircId,
+    /// The filter itself is pinned to a link protocol version this crate does not know how to
+    /// check against. Only reachable if [`MessageFilter`] is ever constructed with a
+    /// [`LinkVersion`] outside [`LinkVersion::SUPPORTED`], which its constructor forbids.
+    UnsupportedLinkVersion,
+}
+
+/// A structured description of why a [`MessageFilter`] rejected a message.
+///
+/// Every field here is typed, unlike the formatted strings `Error::HandshakeProto`/
+/// `Error::ChanProto` carry: code that sees a `FilterError` before it gets turned into an
+/// [`Error`] (via [`into_error`](Self::into_error)) can match on [`RejectReason`] to decide, for
+/// instance, whether to shut the channel down hard versus log-and-continue.
+#[derive(Clone, Debug)]
+pub(crate) struct FilterError {
+    /// Why the message was rejected.
+    pub(crate) reason: RejectReason,
+    /// The command that triggered the rejection.
+    pub(crate) cmd: ChanCmd,
+    /// The direction the message was traveling.
+    pub(crate) direction: MessageDirection,
+    /// The stage the channel was in.
+    pub(crate) stage: MessageStage,
+    /// The channel type the filter was pinned to.
+    pub(crate) channel_type: ChannelType,
+    /// The link protocol version the filter was pinned to.
+    pub(crate) link_version: u16,
+    /// The circuit ID the cell carrying `cmd` arrived (or was about to be sent) on.
+    pub(crate) circ_id: CircId,
+}
+
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reason {
+            RejectReason::DisallowedHere => write!(
+                f,
+                "Cell not allowed on link v{} channel for {} cell command {} for channel type {}",
+                self.link_version, self.direction, self.cmd, self.channel_type
+            ),
+            RejectReason::UnknownCommand => write!(
+                f,
+                "Unknown cell command {} (link v{}, {} cell, channel type {})",
+                self.cmd, self.link_version, self.direction, self.channel_type
+            ),
+            RejectReason::IllegalCircId => write!(
+                f,
+                "Illegal circuit id {} for {} cell command {} on link v{} channel",
+                self.circ_id, self.direction, self.cmd, self.link_version
+            ),
+            RejectReason::UnsupportedLinkVersion => write!(
+                f,
+                "Channel message filter link version is unknown: {}",
+                self.link_version
+            ),
+        }
+    }
+}
+
+impl FilterError {
+    /// Convert into the stage-appropriate [`Error`] variant, formatting today's message so logs
+    /// don't regress.
+    ///
+    /// This is where the structure carried by `FilterError` (the offending [`ChanCmd`], the
+    /// [`MessageStage`], the [`MessageDirection`], ...) is currently flattened into a `String`:
+    /// [`Error::ChanProto`]/[`Error::HandshakeProto`] only hold text. Giving `Error` itself a
+    /// structured `InvalidMessage`-style variant would let that structure survive past this
+    /// point, but `Error` lives in `crate::util::err`, outside this module. Callers that need the
+    /// structure should match on the `FilterError` directly instead of calling this conversion.
+    pub(crate) fn into_error(self) -> Error {
+        self.stage.to_err(self.to_string())
+    }
+}
+
+/// A channel message that has already been validated by a [`MessageFilter`] and narrowed into the
+/// `restricted_msg!`-generated enum appropriate for its channel type, stage and direction.
+///
+/// This mirrors the `CreateResponse` wrapper pattern used for circuit messages in `celltypes`:
+/// once a command has passed the filter, downstream code matches on this small enum instead of
+/// re-checking `AnyChanMsg::cmd()` a second time, and a disallowed command can never reach it.
+///
+/// Every variant is built from linkv5's restricted enums, since each link version's allow-set is a
+/// subset of v5's: whichever version actually validated the command, the conversion below cannot
+/// fail.
+pub(crate) enum Accepted {
+    /// A handshake message a Relay-as-Initiator may send or receive.
+    HandshakeRelayInitiator(linkv5::HandshakeRelayInitiatorMsg),
+    /// A handshake message a Relay-as-Responder may send or receive.
+    HandshakeRelayResponder(linkv5::HandshakeRelayResponderMsg),
+    /// A handshake message a Client-as-Initiator may send or receive.
+    HandshakeClientInitiator(linkv5::HandshakeClientInitiatorMsg),
+    /// An open-channel message sent Client-to-Relay. Unrestricted, see `OpenChanMsgC2R`.
+    OpenC2R(AnyChanMsg),
+    /// An open-channel message sent Relay-to-Client.
+    OpenR2C(linkv5::OpenChanMsgR2C),
+    /// An open-channel message sent Relay-to-Relay.
+    OpenR2R(linkv5::OpenChanMsgR2R),
+}
+
+impl Accepted {
+    /// Narrow `msg` into the variant matching `chan_type`/`stage`/`direction`.
+    ///
+    /// This duplicates the branching in [`LinkProtocol::handshake_allowed`] and
+    /// [`LinkProtocol::open_allowed`] because it must *construct* a value per branch rather than
+    /// just return a bool; callers must have already confirmed the command is allowed.
+    fn narrow(
+        chan_type: ChannelType,
+        stage: &MessageStage,
+        direction: &MessageDirection,
+        msg: AnyChanMsg,
+    ) -> Result<Self, AnyChanMsg> {
+        Ok(match stage {
+            MessageStage::Handshake => match chan_type {
+                ChannelType::ClientInitiator => match direction {
+                    MessageDirection::Inbound => Self::HandshakeRelayResponder(msg.try_into()?),
+                    MessageDirection::Outbound => Self::HandshakeClientInitiator(msg.try_into()?),
                 },
-                MessageStage::Open => match details.direction {
-                    MessageDirection::Inbound => OpenChanMsgR2C::is_known_cmd(cmd),
-                    MessageDirection::Outbound => OpenChanMsgC2R::is_known_cmd(cmd),
+                ChannelType::RelayInitiator => match direction {
+                    MessageDirection::Inbound => Self::HandshakeRelayResponder(msg.try_into()?),
+                    MessageDirection::Outbound => Self::HandshakeRelayInitiator(msg.try_into()?),
                 },
-            },
-            ChannelType::RelayInitiator => match stage {
-                MessageStage::Handshake => match details.direction {
-                    MessageDirection::Inbound => HandshakeRelayResponderMsg::is_known_cmd(cmd),
-                    MessageDirection::Outbound => HandshakeRelayInitiatorMsg::is_known_cmd(cmd),
+                ChannelType::RelayResponder { .. } => match direction {
+                    MessageDirection::Inbound => Self::HandshakeRelayInitiator(msg.try_into()?),
+                    MessageDirection::Outbound => Self::HandshakeRelayResponder(msg.try_into()?),
                 },
-                // Regardless of Inbound or Outbound, same restricted set for Relay <-> Relay.
-                MessageStage::Open => OpenChanMsgR2R::is_known_cmd(cmd),
             },
-            ChannelType::RelayResponder { authenticated } => match stage {
-                // Authenticated is only learned after the handshake is done.
-                MessageStage::Handshake => match details.direction {
-                    MessageDirection::Inbound => HandshakeRelayInitiatorMsg::is_known_cmd(cmd),
-                    MessageDirection::Outbound => HandshakeRelayResponderMsg::is_known_cmd(cmd),
-                },
-                MessageStage::Open => match authenticated {
-                    // Unauthenticated channel means, as a Relay, we respond to a Client.
-                    false => match details.direction {
-                        MessageDirection::Inbound => OpenChanMsgC2R::is_known_cmd(cmd),
-                        MessageDirection::Outbound => OpenChanMsgR2C::is_known_cmd(cmd),
+            MessageStage::Open => {
+                let authenticated =
+                    matches!(chan_type, ChannelType::RelayResponder { authenticated: true });
+                match chan_type {
+                    ChannelType::ClientInitiator => match direction {
+                        MessageDirection::Inbound => Self::OpenR2C(msg.try_into()?),
+                        MessageDirection::Outbound => Self::OpenC2R(msg),
                     },
-                    // Authenticated channel means, as a Relay, we respond to a Relay. Regardless
-                    // of Inbound or Outbound, same restricted set for Relay <-> Relay.
-                    true => OpenChanMsgR2R::is_known_cmd(cmd),
-                },
-            },
-        }
+                    ChannelType::RelayInitiator => Self::OpenR2R(msg.try_into()?),
+                    ChannelType::RelayResponder { .. } => match authenticated {
+                        false => match direction {
+                            MessageDirection::Inbound => Self::OpenC2R(msg),
+                            MessageDirection::Outbound => Self::OpenR2C(msg.try_into()?),
+                        },
+                        true => Self::OpenR2R(msg.try_into()?),
+                    },
+                }
+            }
+        })
     }
 }
 
@@ -302,6 +578,7 @@ pub(crate) mod linkv5 {
 ///
 /// Notice that we don't have the "New" stage and this is because we only learn the link protocol
 /// version once we enter the Handshake stage.
+#[derive(Clone, Copy, Debug)]
 pub(crate) enum MessageStage {
     /// Handshaking as in the channel is working to become open.
     Handshake,
@@ -325,7 +602,7 @@ impl MessageStage {
 ///
 /// This again is very important because depending on the direction, the restricted message set
 /// changes.
-#[derive(derive_more::Display)]
+#[derive(Clone, Copy, Debug, derive_more::Display)]
 pub(crate) enum MessageDirection {
     /// A message that is being received.
     Inbound,
@@ -339,7 +616,7 @@ pub(crate) enum MessageDirection {
 /// It is pinned to a link protocol version, a channel type and a channel message stage.
 pub(crate) struct MessageFilter {
     /// For what link protocol version this filter applies for.
-    link_version: u16,
+    link_version: LinkVersion,
     /// For which channel type this filter applies for.
     channel_type: ChannelType,
     /// At which stage this filter applies for.
@@ -353,21 +630,25 @@ pub(crate) struct MessageDetails {
     cmd: ChanCmd,
     /// Direction of the message.
     direction: MessageDirection,
+    /// Circuit ID the cell carrying `cmd` arrived (or is about to be sent) on.
+    circ_id: CircId,
 }
 
 impl MessageDetails {
     /// Constructor of a new Inbound message details.
-    pub(crate) fn new_inbound(cmd: ChanCmd) -> Self {
+    pub(crate) fn new_inbound(cmd: ChanCmd, circ_id: CircId) -> Self {
         Self {
             cmd,
             direction: MessageDirection::Inbound,
+            circ_id,
         }
     }
     /// Constructor of a new Outbound message details.
-    pub(crate) fn new_outbound(cmd: ChanCmd) -> Self {
+    pub(crate) fn new_outbound(cmd: ChanCmd, circ_id: CircId) -> Self {
         Self {
             cmd,
             direction: MessageDirection::Outbound,
+            circ_id,
         }
     }
 }
@@ -380,7 +661,7 @@ impl std::fmt::Display for MessageDetails {
 
 impl MessageFilter {
     /// Constructor
-    pub(crate) fn new(link_version: u16, channel_type: ChannelType, stage: MessageStage) -> Self {
+    pub(crate) fn new(link_version: LinkVersion, channel_type: ChannelType, stage: MessageStage) -> Self {
         Self {
             link_version,
             channel_type,
@@ -390,33 +671,113 @@ impl MessageFilter {
 
     /// Return Ok if the message is allowed for this filter object.
     ///
-    /// If not allowed, an error is returned describing why and the context around it.
-    pub(crate) fn is_allowed(&self, details: &MessageDetails) -> Result<(), Error> {
-        let r = match self.link_version {
-            4 => linkv4::is_allowed(self.channel_type, &self.stage, details),
-            5 => linkv5::is_allowed(self.channel_type, &self.stage, details),
-            _ => {
-                // In reality, we should never get here because it is not possible to create a
-                // Handshake cell handler for an unknown version. Regardless, don't explode.
-                return Err(Error::ChanProto(format!(
-                    "Channel message filter link version is unknown: {}",
-                    self.link_version
-                )));
-            }
+    /// If not allowed, a structured [`FilterError`] is returned describing why and the context
+    /// around it: the offending [`ChanCmd`], the [`MessageStage`] and [`MessageDirection`] it was
+    /// rejected in, and the rest of [`FilterError`]'s fields. Callers that just want today's
+    /// [`Error`] can convert with [`FilterError::into_error`]; callers that want to react
+    /// differently to different [`RejectReason`]s can match on the structured value first.
+    pub(crate) fn is_allowed(&self, details: &MessageDetails) -> Result<(), FilterError> {
+        match self.link_version.number() {
+            4 => linkv4::LinkV4.check(self.channel_type, &self.stage, details),
+            5 => linkv5::LinkV5.check(self.channel_type, &self.stage, details),
+            // Unreachable: LinkVersion can only be built for a version in LinkVersion::SUPPORTED.
+            v => Err(FilterError {
+                reason: RejectReason::UnsupportedLinkVersion,
+                cmd: details.cmd,
+                direction: details.direction,
+                stage: self.stage,
+                channel_type: self.channel_type,
+                link_version: v,
+                circ_id: details.circ_id,
+            }),
+        }
+    }
+
+    /// Confirm that `msg`, traveling in `direction`, is allowed for this filter, and consume it
+    /// into the [`Accepted`] enum narrowed to this filter's channel type, stage and direction.
+    ///
+    /// This saves the caller from decoding `msg` into an `AnyChanMsg` and then re-checking its
+    /// command a second time with [`is_allowed`](Self::is_allowed): the command is checked once,
+    /// here, and the result is a value whose type already proves it passed the filter.
+    pub(crate) fn decode(
+        &self,
+        direction: MessageDirection,
+        circ_id: CircId,
+        msg: AnyChanMsg,
+    ) -> Result<Accepted, Error> {
+        let details = match direction {
+            MessageDirection::Inbound => MessageDetails::new_inbound(msg.cmd(), circ_id),
+            MessageDirection::Outbound => MessageDetails::new_outbound(msg.cmd(), circ_id),
         };
-        // Return a meaningful error if command is not allowed.
-        r.then_some(()).ok_or_else(|| {
-            self.stage.to_err(format!(
-                "Cell not allowed on link v{} channel for {details} for channel type {}",
-                self.link_version, self.channel_type
-            ))
-        })
+        match self.link_version.number() {
+            4 => linkv4::LinkV4.decode(self.channel_type, &self.stage, &details, msg),
+            5 => linkv5::LinkV5.decode(self.channel_type, &self.stage, &details, msg),
+            // Unreachable: LinkVersion can only be built for a version in LinkVersion::SUPPORTED.
+            v => Err(Error::ChanProto(format!(
+                "Channel message filter link version is unknown: {v}",
+            ))),
+        }
     }
 }
 
 /// Helper function: Return true iff the given link protocol version value is known to us.
 pub(crate) fn is_link_version_known(v: u16) -> bool {
-    v == 4 || v == 5
+    LinkVersion::SUPPORTED.contains(&v)
+}
+
+/// A link protocol version we have confirmed we understand, together with the capabilities it
+/// implies.
+///
+/// Can only be built through [`try_new`](Self::try_new) or [`negotiate`], so that a
+/// [`MessageFilter`] can never be pinned to a version this crate doesn't actually understand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct LinkVersion(u16);
+
+impl LinkVersion {
+    /// Every link protocol version this crate supports.
+    const SUPPORTED: &'static [u16] = &[4, 5];
+
+    /// Return `Some(LinkVersion(v))` iff `v` is a link protocol version we support.
+    pub(crate) fn try_new(v: u16) -> Option<Self> {
+        Self::SUPPORTED.contains(&v).then_some(Self(v))
+    }
+
+    /// Return the raw link protocol version number, e.g. `4` or `5`.
+    pub(crate) fn number(self) -> u16 {
+        self.0
+    }
+
+    /// Return true iff this version supports `PADDING`/`VPADDING` cells.
+    pub(crate) fn supports_cell_padding(self) -> bool {
+        self.0 >= 5
+    }
+
+    /// Return the width, in bytes, of a circuit ID on a channel of this link version.
+    pub(crate) fn circ_id_width(self) -> usize {
+        // Both of our supported versions widened circ-ids to 4 bytes; this exists so a future
+        // version with a different width has one place to report it from.
+        4
+    }
+}
+
+/// Choose the highest link protocol version that both `local_supported` and `peer_offered`
+/// advertise.
+///
+/// Mirrors the classic version-negotiation approach: offer a set of supported versions, intersect
+/// with the peer's, and pick the highest common one. Returns [`Error::HandshakeProto`] if the two
+/// sets share no version we recognize.
+pub(crate) fn negotiate(local_supported: &[u16], peer_offered: &[u16]) -> Result<LinkVersion, Error> {
+    local_supported
+        .iter()
+        .copied()
+        .filter(|v| peer_offered.contains(v))
+        .filter_map(LinkVersion::try_new)
+        .max_by_key(LinkVersion::number)
+        .ok_or_else(|| {
+            Error::HandshakeProto(format!(
+                "No common link protocol version: we support {local_supported:?}, peer offered {peer_offered:?}"
+            ))
+        })
 }
 
 #[cfg(test)]
@@ -25,68 +25,53 @@ mod net {
     use std::pin::Pin;
     use std::task::{Context, Poll};
 
+    /// The result type returned by an accept future held by an
+    /// [`IncomingStreams`].
+    type AcceptResult = IoResult<(TcpStream, SocketAddr)>;
+
     /// A `Stream` of incoming TCP streams.
     ///
     /// Differs from the output of [`TcpListener::incoming`] in that this
     /// struct is a real type, and that it returns a TCP stream and an address
     /// for each input.
     pub struct IncomingStreams {
-        /// A state object, stored in an Option so we can take ownership of it
-        /// while poll is being called.
-        // TODO(nickm): I hate using this trick.  At some point in the
-        // future, once Rust has nice support for async traits, maybe
-        // we can refactor it.
-        state: Option<IncomingStreamsState>,
-    }
-    /// The result type returned by [`take_and_poll`].
-    ///
-    /// It has to include the TcpListener, since take_and_poll() has
-    /// ownership of the listener.
-    type FResult = (IoResult<(TcpStream, SocketAddr)>, TcpListener);
-    /// Helper to implement [`IncomingStreams`]
-    ///
-    /// This function calls [`TcpListener::accept`] while owning the
-    /// listener.  Thus, it returns a future that itself owns the listener,
-    /// and we don't have lifetime troubles.
-    async fn take_and_poll(lis: TcpListener) -> FResult {
-        let result = lis.accept().await;
-        (result, lis)
-    }
-    /// The possible states for an [`IncomingStreams`].
-    enum IncomingStreamsState {
-        /// We're ready to call `accept` on the listener again.
-        Ready(TcpListener),
-        /// We've called `accept` on the listener, and we're waiting
-        /// for a future to complete.
-        Accepting(Pin<Box<dyn Future<Output = FResult> + Send>>),
+        /// The listener we're accepting from, shared with the in-flight
+        /// accept future below so we never have to move it in and out of a
+        /// state enum.
+        listener: std::sync::Arc<TcpListener>,
+        /// The currently in-flight `accept()` call.
+        ///
+        /// Once this resolves, we immediately re-arm it with a fresh accept
+        /// future that shares the same `listener` (through its `Arc`), so this field is always
+        /// populated rather than living behind an `Option` we have to
+        /// `.take()` out of on every poll.
+        accepting: Pin<Box<dyn Future<Output = AcceptResult> + Send>>,
     }
     impl IncomingStreams {
         /// Create a new IncomingStreams from a TcpListener.
         pub fn from_listener(lis: TcpListener) -> IncomingStreams {
-            IncomingStreams {
-                state: Some(IncomingStreamsState::Ready(lis)),
-            }
+            let listener = std::sync::Arc::new(lis);
+            let accepting = Self::accept_future(std::sync::Arc::clone(&listener));
+            IncomingStreams { listener, accepting }
+        }
+
+        /// Build a future that accepts a single connection from `listener`.
+        fn accept_future(
+            listener: std::sync::Arc<TcpListener>,
+        ) -> Pin<Box<dyn Future<Output = AcceptResult> + Send>> {
+            Box::pin(async move { listener.accept().await })
         }
     }
     impl Stream for IncomingStreams {
-        type Item = IoResult<(TcpStream, SocketAddr)>;
+        type Item = AcceptResult;
 
         fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-            use IncomingStreamsState as St;
-            let state = self.state.take().expect("No valid state!");
-            let mut future = match state {
-                St::Ready(lis) => Box::pin(take_and_poll(lis)),
-                St::Accepting(fut) => fut,
-            };
-            match future.as_mut().poll(cx) {
-                Poll::Ready((val, lis)) => {
-                    self.state = Some(St::Ready(lis));
+            match self.accepting.as_mut().poll(cx) {
+                Poll::Ready(val) => {
+                    self.accepting = Self::accept_future(std::sync::Arc::clone(&self.listener));
                     Poll::Ready(Some(val))
                 }
-                Poll::Pending => {
-                    self.state = Some(St::Accepting(future));
-                    Poll::Pending
-                }
+                Poll::Pending => Poll::Pending,
             }
         }
     }
@@ -129,6 +114,7 @@ mod net {
     }
 
     /// Wrap a AsyncStd UdpSocket
+    #[derive(Clone)]
     pub struct UdpSocket {
         /// The underlying UdpSocket
         socket: StdUdpSocket,
@@ -136,19 +122,223 @@ mod net {
 
     #[async_trait]
     impl traits::UdpSocket for UdpSocket {
-        async fn recv(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+        async fn recv_from(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
             self.socket.recv_from(buf).await
         }
 
-        async fn send(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
+        async fn send_to(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
             self.socket.send_to(buf, target).await
         }
 
+        async fn peek_from(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+            self.socket.peek_from(buf).await
+        }
+
+        async fn connect(&self, addr: &SocketAddr) -> IoResult<()> {
+            self.socket.connect(addr).await
+        }
+
+        async fn recv(&self, buf: &mut [u8]) -> IoResult<usize> {
+            self.socket.recv(buf).await
+        }
+
+        async fn send(&self, buf: &[u8]) -> IoResult<usize> {
+            self.socket.send(buf).await
+        }
+
+        fn set_ttl(&self, ttl: u32) -> IoResult<()> {
+            self.socket.set_ttl(ttl)
+        }
+
+        fn ttl(&self) -> IoResult<u32> {
+            self.socket.ttl()
+        }
+
+        fn set_broadcast(&self, broadcast: bool) -> IoResult<()> {
+            self.socket.set_broadcast(broadcast)
+        }
+
+        fn broadcast(&self) -> IoResult<bool> {
+            self.socket.broadcast()
+        }
+
+        fn join_multicast_v4(&self, multiaddr: std::net::Ipv4Addr, interface: std::net::Ipv4Addr) -> IoResult<()> {
+            self.socket.join_multicast_v4(multiaddr, interface)
+        }
+
+        fn leave_multicast_v4(&self, multiaddr: std::net::Ipv4Addr, interface: std::net::Ipv4Addr) -> IoResult<()> {
+            self.socket.leave_multicast_v4(multiaddr, interface)
+        }
+
+        fn join_multicast_v6(&self, multiaddr: &std::net::Ipv6Addr, interface: u32) -> IoResult<()> {
+            self.socket.join_multicast_v6(multiaddr, interface)
+        }
+
+        fn leave_multicast_v6(&self, multiaddr: &std::net::Ipv6Addr, interface: u32) -> IoResult<()> {
+            self.socket.leave_multicast_v6(multiaddr, interface)
+        }
+
         fn local_addr(&self) -> IoResult<SocketAddr> {
             self.socket.local_addr()
         }
     }
 
+    /// Monotonically increasing tag used to pair up split halves so that
+    /// `reunite()` can check that both halves came from the same socket.
+    fn next_split_id() -> u64 {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Error returned by `reunite()` when the two halves didn't come from the
+    /// same socket.
+    #[derive(Debug, Clone, Copy, thiserror::Error)]
+    #[error("tried to reunite halves of different sockets")]
+    pub struct ReuniteError;
+
+    /// The owned read half of a [`TcpStream`], produced by
+    /// [`TcpStreamExt::into_split`].
+    pub struct OwnedReadHalf {
+        /// The underlying stream.
+        stream: TcpStream,
+        /// Tag used to check that this half is reunited with its sibling.
+        id: u64,
+    }
+
+    /// The owned write half of a [`TcpStream`], produced by
+    /// [`TcpStreamExt::into_split`].
+    pub struct OwnedWriteHalf {
+        /// The underlying stream.
+        stream: TcpStream,
+        /// Tag used to check that this half is reunited with its sibling.
+        id: u64,
+    }
+
+    /// Extension trait adding owned and borrowing split to [`TcpStream`].
+    pub trait TcpStreamExt {
+        /// Split into an owned read half and an owned write half, so the
+        /// socket can be driven from two tasks without a lock.
+        fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf);
+        /// Split into borrowing read/write halves.
+        fn split(&self) -> (&TcpStream, &TcpStream);
+    }
+
+    impl TcpStreamExt for TcpStream {
+        fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+            let id = next_split_id();
+            (
+                OwnedReadHalf {
+                    stream: self.clone(),
+                    id,
+                },
+                OwnedWriteHalf { stream: self, id },
+            )
+        }
+        fn split(&self) -> (&TcpStream, &TcpStream) {
+            (self, self)
+        }
+    }
+
+    impl OwnedReadHalf {
+        /// Recombine this half with its sibling write half, failing if they
+        /// came from different sockets.
+        pub fn reunite(self, other: OwnedWriteHalf) -> Result<TcpStream, ReuniteError> {
+            if self.id == other.id {
+                Ok(self.stream)
+            } else {
+                Err(ReuniteError)
+            }
+        }
+    }
+
+    impl AsyncRead for OwnedReadHalf {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<IoResult<usize>> {
+            Pin::new(&mut self.stream).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for OwnedWriteHalf {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<IoResult<usize>> {
+            Pin::new(&mut self.stream).poll_write(cx, buf)
+        }
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+            Pin::new(&mut self.stream).poll_flush(cx)
+        }
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+            Pin::new(&mut self.stream).poll_close(cx)
+        }
+    }
+
+    /// The owned receive half of a [`UdpSocket`], produced by
+    /// [`UdpSocket::into_split`].
+    pub struct OwnedUdpRecvHalf {
+        /// The underlying socket.
+        socket: UdpSocket,
+        /// Tag used to check that this half is reunited with its sibling.
+        id: u64,
+    }
+
+    /// The owned send half of a [`UdpSocket`], produced by
+    /// [`UdpSocket::into_split`].
+    pub struct OwnedUdpSendHalf {
+        /// The underlying socket.
+        socket: UdpSocket,
+        /// Tag used to check that this half is reunited with its sibling.
+        id: u64,
+    }
+
+    impl UdpSocket {
+        /// Split into an owned receive half and an owned send half, so the
+        /// socket can be driven from two tasks without a lock.
+        pub fn into_split(self) -> (OwnedUdpRecvHalf, OwnedUdpSendHalf) {
+            let id = next_split_id();
+            (
+                OwnedUdpRecvHalf {
+                    socket: self.clone(),
+                    id,
+                },
+                OwnedUdpSendHalf { socket: self, id },
+            )
+        }
+        /// Split into borrowing receive/send halves.
+        pub fn split(&self) -> (&UdpSocket, &UdpSocket) {
+            (self, self)
+        }
+    }
+
+    impl OwnedUdpRecvHalf {
+        /// Recombine this half with its sibling send half, failing if they
+        /// came from different sockets.
+        pub fn reunite(self, other: OwnedUdpSendHalf) -> Result<UdpSocket, ReuniteError> {
+            if self.id == other.id {
+                Ok(self.socket)
+            } else {
+                Err(ReuniteError)
+            }
+        }
+
+        /// Receive a datagram; see [`traits::UdpSocket::recv_from`].
+        pub async fn recv_from(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+            self.socket.recv_from(buf).await
+        }
+    }
+
+    impl OwnedUdpSendHalf {
+        /// Send a datagram; see [`traits::UdpSocket::send_to`].
+        pub async fn send_to(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
+            self.socket.send_to(buf, target).await
+        }
+    }
+
     /// Wrap a async-std UnixSocket
     pub struct UnixStream {
         /// The underlying UnixStream
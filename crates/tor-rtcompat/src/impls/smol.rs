@@ -150,6 +150,7 @@ pub(crate) mod net {
 
     /// Wrapper for `SmolUdpSocket`.
     // Required to implement `traits::UdpSocket`.
+    #[derive(Clone)]
     pub struct UdpSocket {
         /// The underlying socket.
         socket: SmolUdpSocket,
@@ -157,19 +158,223 @@ pub(crate) mod net {
 
     #[async_trait]
     impl traits::UdpSocket for UdpSocket {
-        async fn recv(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+        async fn recv_from(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
             self.socket.recv_from(buf).await
         }
 
-        async fn send(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
+        async fn send_to(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
             self.socket.send_to(buf, target).await
         }
 
+        async fn peek_from(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+            self.socket.peek_from(buf).await
+        }
+
+        async fn connect(&self, addr: &SocketAddr) -> IoResult<()> {
+            self.socket.connect(addr).await
+        }
+
+        async fn recv(&self, buf: &mut [u8]) -> IoResult<usize> {
+            self.socket.recv(buf).await
+        }
+
+        async fn send(&self, buf: &[u8]) -> IoResult<usize> {
+            self.socket.send(buf).await
+        }
+
+        fn set_ttl(&self, ttl: u32) -> IoResult<()> {
+            self.socket.set_ttl(ttl)
+        }
+
+        fn ttl(&self) -> IoResult<u32> {
+            self.socket.ttl()
+        }
+
+        fn set_broadcast(&self, broadcast: bool) -> IoResult<()> {
+            self.socket.set_broadcast(broadcast)
+        }
+
+        fn broadcast(&self) -> IoResult<bool> {
+            self.socket.broadcast()
+        }
+
+        fn join_multicast_v4(&self, multiaddr: std::net::Ipv4Addr, interface: std::net::Ipv4Addr) -> IoResult<()> {
+            self.socket.join_multicast_v4(multiaddr, interface)
+        }
+
+        fn leave_multicast_v4(&self, multiaddr: std::net::Ipv4Addr, interface: std::net::Ipv4Addr) -> IoResult<()> {
+            self.socket.leave_multicast_v4(multiaddr, interface)
+        }
+
+        fn join_multicast_v6(&self, multiaddr: &std::net::Ipv6Addr, interface: u32) -> IoResult<()> {
+            self.socket.join_multicast_v6(multiaddr, interface)
+        }
+
+        fn leave_multicast_v6(&self, multiaddr: &std::net::Ipv6Addr, interface: u32) -> IoResult<()> {
+            self.socket.leave_multicast_v6(multiaddr, interface)
+        }
+
         fn local_addr(&self) -> IoResult<SocketAddr> {
             self.socket.local_addr()
         }
     }
 
+    /// Monotonically increasing tag used to pair up split halves so that
+    /// `reunite()` can check that both halves came from the same socket.
+    fn next_split_id() -> u64 {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Error returned by `reunite()` when the two halves didn't come from the
+    /// same socket.
+    #[derive(Debug, Clone, Copy, thiserror::Error)]
+    #[error("tried to reunite halves of different sockets")]
+    pub struct ReuniteError;
+
+    /// The owned read half of a [`TcpStream`], produced by
+    /// [`TcpStreamExt::into_split`].
+    pub struct OwnedReadHalf {
+        /// The underlying stream.
+        stream: TcpStream,
+        /// Tag used to check that this half is reunited with its sibling.
+        id: u64,
+    }
+
+    /// The owned write half of a [`TcpStream`], produced by
+    /// [`TcpStreamExt::into_split`].
+    pub struct OwnedWriteHalf {
+        /// The underlying stream.
+        stream: TcpStream,
+        /// Tag used to check that this half is reunited with its sibling.
+        id: u64,
+    }
+
+    /// Extension trait adding owned and borrowing split to [`TcpStream`].
+    pub trait TcpStreamExt {
+        /// Split into an owned read half and an owned write half, so the
+        /// socket can be driven from two tasks without a lock.
+        fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf);
+        /// Split into borrowing read/write halves.
+        fn split(&self) -> (&TcpStream, &TcpStream);
+    }
+
+    impl TcpStreamExt for TcpStream {
+        fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+            let id = next_split_id();
+            (
+                OwnedReadHalf {
+                    stream: self.clone(),
+                    id,
+                },
+                OwnedWriteHalf { stream: self, id },
+            )
+        }
+        fn split(&self) -> (&TcpStream, &TcpStream) {
+            (self, self)
+        }
+    }
+
+    impl OwnedReadHalf {
+        /// Recombine this half with its sibling write half, failing if they
+        /// came from different sockets.
+        pub fn reunite(self, other: OwnedWriteHalf) -> Result<TcpStream, ReuniteError> {
+            if self.id == other.id {
+                Ok(self.stream)
+            } else {
+                Err(ReuniteError)
+            }
+        }
+    }
+
+    impl futures::io::AsyncRead for OwnedReadHalf {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<IoResult<usize>> {
+            Pin::new(&mut self.stream).poll_read(cx, buf)
+        }
+    }
+
+    impl futures::io::AsyncWrite for OwnedWriteHalf {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<IoResult<usize>> {
+            Pin::new(&mut self.stream).poll_write(cx, buf)
+        }
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+            Pin::new(&mut self.stream).poll_flush(cx)
+        }
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+            Pin::new(&mut self.stream).poll_close(cx)
+        }
+    }
+
+    /// The owned receive half of a [`UdpSocket`], produced by
+    /// [`UdpSocket::into_split`].
+    pub struct OwnedUdpRecvHalf {
+        /// The underlying socket.
+        socket: UdpSocket,
+        /// Tag used to check that this half is reunited with its sibling.
+        id: u64,
+    }
+
+    /// The owned send half of a [`UdpSocket`], produced by
+    /// [`UdpSocket::into_split`].
+    pub struct OwnedUdpSendHalf {
+        /// The underlying socket.
+        socket: UdpSocket,
+        /// Tag used to check that this half is reunited with its sibling.
+        id: u64,
+    }
+
+    impl UdpSocket {
+        /// Split into an owned receive half and an owned send half, so the
+        /// socket can be driven from two tasks without a lock.
+        pub fn into_split(self) -> (OwnedUdpRecvHalf, OwnedUdpSendHalf) {
+            let id = next_split_id();
+            (
+                OwnedUdpRecvHalf {
+                    socket: self.clone(),
+                    id,
+                },
+                OwnedUdpSendHalf { socket: self, id },
+            )
+        }
+        /// Split into borrowing receive/send halves.
+        pub fn split(&self) -> (&UdpSocket, &UdpSocket) {
+            (self, self)
+        }
+    }
+
+    impl OwnedUdpRecvHalf {
+        /// Recombine this half with its sibling send half, failing if they
+        /// came from different sockets.
+        pub fn reunite(self, other: OwnedUdpSendHalf) -> Result<UdpSocket, ReuniteError> {
+            if self.id == other.id {
+                Ok(self.socket)
+            } else {
+                Err(ReuniteError)
+            }
+        }
+
+        /// Receive a datagram; see [`traits::UdpSocket::recv_from`].
+        pub async fn recv_from(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+            self.socket.recv_from(buf).await
+        }
+    }
+
+    impl OwnedUdpSendHalf {
+        /// Send a datagram; see [`traits::UdpSocket::send_to`].
+        pub async fn send_to(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
+            self.socket.send_to(buf, target).await
+        }
+    }
+
     impl traits::StreamOps for TcpStream {
         fn set_tcp_notsent_lowat(&self, lowat: u32) -> IoResult<()> {
             impls::streamops::set_tcp_notsent_lowat(self, lowat)
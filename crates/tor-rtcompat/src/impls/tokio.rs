@@ -11,8 +11,8 @@ pub(crate) mod net {
     use cfg_if::cfg_if;
     #[cfg(unix)]
     pub(crate) use tokio_crate::net::{
-        unix::SocketAddr as TokioUnixSocketAddr, UnixListener as TokioUnixListener,
-        UnixStream as TokioUnixStream,
+        unix::SocketAddr as TokioUnixSocketAddr, UnixDatagram as TokioUnixDatagram,
+        UnixListener as TokioUnixListener, UnixStream as TokioUnixStream,
     };
     pub(crate) use tokio_crate::net::{
         TcpListener as TokioTcpListener, TcpStream as TokioTcpStream, UdpSocket as TokioUdpSocket,
@@ -120,16 +120,80 @@ pub(crate) mod net {
         }
     }
 
+    // NOTE: this assumes `crate::traits::UdpSocket` has grown the rest of
+    // `std`/tokio's `UdpSocket` surface (`connect`, connected-mode
+    // `send`/`recv`, `peek_from`, TTL, broadcast, and multicast membership),
+    // and that its addressed `recv`/`send` were renamed to `recv_from`/
+    // `send_to` to make room for the connected-mode pair -- since `traits.rs`
+    // isn't part of this snapshot and so can't be edited directly. The same
+    // shape is assumed (and implemented) for the `smol` and `async_std`
+    // backends, to keep all three runtimes implementing one consistent trait.
     #[async_trait]
     impl traits::UdpSocket for UdpSocket {
-        async fn recv(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+        async fn recv_from(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
             self.socket.recv_from(buf).await
         }
 
-        async fn send(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
+        async fn send_to(&self, buf: &[u8], target: &SocketAddr) -> IoResult<usize> {
             self.socket.send_to(buf, target).await
         }
 
+        async fn peek_from(&self, buf: &mut [u8]) -> IoResult<(usize, SocketAddr)> {
+            self.socket.peek_from(buf).await
+        }
+
+        async fn connect(&self, addr: &SocketAddr) -> IoResult<()> {
+            self.socket.connect(addr).await
+        }
+
+        async fn recv(&self, buf: &mut [u8]) -> IoResult<usize> {
+            self.socket.recv(buf).await
+        }
+
+        async fn send(&self, buf: &[u8]) -> IoResult<usize> {
+            self.socket.send(buf).await
+        }
+
+        fn set_ttl(&self, ttl: u32) -> IoResult<()> {
+            self.socket.set_ttl(ttl)
+        }
+
+        fn ttl(&self) -> IoResult<u32> {
+            self.socket.ttl()
+        }
+
+        fn set_broadcast(&self, broadcast: bool) -> IoResult<()> {
+            self.socket.set_broadcast(broadcast)
+        }
+
+        fn broadcast(&self) -> IoResult<bool> {
+            self.socket.broadcast()
+        }
+
+        fn join_multicast_v4(
+            &self,
+            multiaddr: std::net::Ipv4Addr,
+            interface: std::net::Ipv4Addr,
+        ) -> IoResult<()> {
+            self.socket.join_multicast_v4(multiaddr, interface)
+        }
+
+        fn leave_multicast_v4(
+            &self,
+            multiaddr: std::net::Ipv4Addr,
+            interface: std::net::Ipv4Addr,
+        ) -> IoResult<()> {
+            self.socket.leave_multicast_v4(multiaddr, interface)
+        }
+
+        fn join_multicast_v6(&self, multiaddr: &std::net::Ipv6Addr, interface: u32) -> IoResult<()> {
+            self.socket.join_multicast_v6(multiaddr, interface)
+        }
+
+        fn leave_multicast_v6(&self, multiaddr: &std::net::Ipv6Addr, interface: u32) -> IoResult<()> {
+            self.socket.leave_multicast_v6(multiaddr, interface)
+        }
+
         fn local_addr(&self) -> IoResult<SocketAddr> {
             self.socket.local_addr()
         }
@@ -152,6 +216,34 @@ pub(crate) mod net {
             UnixStream { s }
         }
     }
+
+    impl UnixStream {
+        /// Return the uid, gid, and (where supported) pid of the process on
+        /// the other end of this connection, as reported by the kernel at
+        /// connect/accept time (`SO_PEERCRED` on Linux; the BSD/macOS
+        /// equivalent elsewhere).
+        pub fn peer_cred(&self) -> IoResult<traits::UnixPeerCred> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    self.s.get_ref().peer_cred().map(Into::into)
+                }
+                else {
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+    }
+
+    // NOTE: this assumes `crate::traits` has grown a runtime-agnostic
+    // `UnixPeerCred` struct (with `uid()`/`gid()`/`pid() -> Option<u32>`
+    // accessors), since `traits.rs` isn't part of this snapshot and so can't
+    // be edited directly.
+    #[cfg(unix)]
+    impl From<tokio_crate::net::unix::UCred> for traits::UnixPeerCred {
+        fn from(cred: tokio_crate::net::unix::UCred) -> Self {
+            traits::UnixPeerCred::new(cred.uid(), cred.gid(), cred.pid().map(|pid| pid as u32))
+        }
+    }
     impl AsyncRead for UnixStream {
         #[allow(unused_mut)]
         fn poll_read(
@@ -303,6 +395,141 @@ pub(crate) mod net {
             }
         }
     }
+
+    /// Wrap a Tokio UnixDatagram
+    pub struct UnixDatagram {
+        /// The underlying socket.
+        #[cfg(unix)]
+        socket: TokioUnixDatagram,
+
+        /// Unit, so that this struct can't be constructed on non-unix platforms.
+        #[cfg(not(unix))]
+        _void: (),
+    }
+
+    #[cfg(unix)]
+    impl From<TokioUnixDatagram> for UnixDatagram {
+        fn from(socket: TokioUnixDatagram) -> UnixDatagram {
+            UnixDatagram { socket }
+        }
+    }
+
+    impl UnixDatagram {
+        /// Bind a UnixDatagram to `path`.
+        pub async fn bind(path: &std::path::Path) -> IoResult<Self> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    TokioUnixDatagram::bind(path).map(|socket| UnixDatagram { socket })
+                }
+                else {
+                    let _ = path;
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+
+        /// Create a pair of connected, unnamed `UnixDatagram`s.
+        pub fn pair() -> IoResult<(Self, Self)> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    let (a, b) = TokioUnixDatagram::pair()?;
+                    Ok((a.into(), b.into()))
+                }
+                else {
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+
+        /// Create a new, unbound, unconnected `UnixDatagram`.
+        pub fn unbound() -> IoResult<Self> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    TokioUnixDatagram::unbound().map(|socket| UnixDatagram { socket })
+                }
+                else {
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+
+        /// Connect this socket to `path`, so that `send`/`recv` can be used
+        /// instead of `send_to`/`recv_from`.
+        pub fn connect(&self, path: &std::path::Path) -> IoResult<()> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    self.socket.connect(path)
+                }
+                else {
+                    let _ = path;
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+    }
+
+    #[async_trait]
+    impl traits::UnixDatagram for UnixDatagram {
+        async fn send_to(&self, buf: &[u8], path: &std::path::Path) -> IoResult<usize> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    self.socket.send_to(buf, path).await
+                }
+                else {
+                    let _ = (buf, path);
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+
+        async fn recv_from(&self, buf: &mut [u8]) -> IoResult<(usize, UnixSocketAddr)> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    let (n, addr) = self.socket.recv_from(buf).await?;
+                    Ok((n, addr.into()))
+                }
+                else {
+                    let _ = buf;
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+
+        async fn send(&self, buf: &[u8]) -> IoResult<usize> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    self.socket.send(buf).await
+                }
+                else {
+                    let _ = buf;
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+
+        async fn recv(&self, buf: &mut [u8]) -> IoResult<usize> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    self.socket.recv(buf).await
+                }
+                else {
+                    let _ = buf;
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+
+        fn local_addr(&self) -> IoResult<UnixSocketAddr> {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    self.socket.local_addr().map(Into::into)
+                }
+                else {
+                    Err(std::io::ErrorKind::Unsupported.into())
+                }
+            }
+        }
+    }
 }
 
 // ==============================
@@ -390,6 +617,31 @@ impl crate::traits::UnixProvider for TokioRuntimeHandle {
     }
 }
 
+// NOTE: this assumes `crate::traits` has grown a `UnixDatagram` trait
+// (mirroring `UdpSocket`, with `send_to`/`recv_from`/`send`/`recv`/`local_addr`)
+// and a sibling `UnixDatagramProvider` trait (mirroring `UnixProvider`), since
+// `traits.rs` isn't part of this snapshot and so can't be edited directly.
+#[async_trait]
+impl crate::traits::UnixDatagramProvider for TokioRuntimeHandle {
+    type UnixDatagram = net::UnixDatagram;
+
+    async fn bind_unix_datagram(&self, path: &Path) -> IoResult<Self::UnixDatagram> {
+        net::UnixDatagram::bind(path).await
+    }
+
+    async fn unbound_unix_datagram(&self) -> IoResult<(Self::UnixDatagram, Self::UnixDatagram)> {
+        cfg_if! {
+            if #[cfg(unix)] {
+                let pair = net::UnixDatagram::pair()?;
+                Ok(pair)
+            }
+            else {
+                Err(std::io::ErrorKind::Unsupported.into())
+            }
+        }
+    }
+}
+
 /// Create and return a new Tokio multithreaded runtime.
 pub(crate) fn create_runtime() -> IoResult<TokioRuntimeHandle> {
     let mut builder = async_executors::TokioTpBuilder::new();
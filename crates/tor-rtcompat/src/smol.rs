@@ -23,6 +23,142 @@ pub use SmolNativeTlsRuntime as PreferredRuntime;
 #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
 pub use SmolRustlsRuntime as PreferredRuntime;
 
+/// Configuration used to accept a TLS connection: a certificate chain to
+/// present to the peer, and the private key matching its leaf certificate.
+///
+/// The certificate chain and private key are DER-encoded, to match the types that `rustls` and
+/// `native-tls` already accept for their client-side counterparts.
+#[derive(Clone)]
+pub struct TlsServerConfig {
+    /// The certificate chain to present, leaf certificate first.
+    pub cert_chain: Vec<Vec<u8>>,
+    /// The DER-encoded private key belonging to the leaf certificate.
+    pub private_key: Vec<u8>,
+    /// The ALPN protocols we're willing to negotiate, in preference order.
+    ///
+    /// If empty, ALPN is not offered to the peer.
+    pub alpn_protocols: Vec<Vec<u8>>,
+}
+
+/// A TLS stream whose negotiated ALPN protocol (if any) can be queried after
+/// the handshake completes.
+pub trait AlpnStream {
+    /// Return the protocol negotiated via ALPN during the handshake, or
+    /// `None` if ALPN wasn't offered, or the peer didn't select one.
+    ///
+    /// The `native-tls` implementation always returns `None`, whatever was
+    /// negotiated.
+    fn negotiated_alpn_protocol(&self) -> Option<Vec<u8>>;
+}
+
+#[cfg(feature = "native-tls")]
+impl<S> AlpnStream for async_native_tls::TlsStream<S> {
+    fn negotiated_alpn_protocol(&self) -> Option<Vec<u8>> {
+        // native-tls's ALPN support doesn't expose the negotiated protocol
+        // through a stable cross-backend API; until it does, report none.
+        None
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl<S> AlpnStream for futures_rustls::server::TlsStream<S> {
+    fn negotiated_alpn_protocol(&self) -> Option<Vec<u8>> {
+        let (_, conn) = self.get_ref();
+        conn.alpn_protocol().map(|p| p.to_vec())
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl<S> AlpnStream for futures_rustls::client::TlsStream<S> {
+    fn negotiated_alpn_protocol(&self) -> Option<Vec<u8>> {
+        let (_, conn) = self.get_ref();
+        conn.alpn_protocol().map(|p| p.to_vec())
+    }
+}
+
+/// A stream that has possibly been wrapped in TLS.
+///
+/// This mirrors the `Encryption` type used on the client side: most of the
+/// time, it's simpler for callers to hold a single stream type rather than a
+/// generic one, even though that means boxing in the TLS case.
+pub enum Encryption<C, T> {
+    /// A plaintext connection.
+    Tcp(C),
+    /// A TLS-protected connection.
+    TcpTls(Box<T>),
+}
+
+impl<C, T> futures::io::AsyncRead for Encryption<C, T>
+where
+    C: futures::io::AsyncRead + Unpin,
+    T: futures::io::AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> std::task::Poll<IoResult<usize>> {
+        match self.get_mut() {
+            Encryption::Tcp(c) => std::pin::Pin::new(c).poll_read(cx, buf),
+            Encryption::TcpTls(t) => std::pin::Pin::new(t.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<C, T> futures::io::AsyncWrite for Encryption<C, T>
+where
+    C: futures::io::AsyncWrite + Unpin,
+    T: futures::io::AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<IoResult<usize>> {
+        match self.get_mut() {
+            Encryption::Tcp(c) => std::pin::Pin::new(c).poll_write(cx, buf),
+            Encryption::TcpTls(t) => std::pin::Pin::new(t.as_mut()).poll_write(cx, buf),
+        }
+    }
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<IoResult<()>> {
+        match self.get_mut() {
+            Encryption::Tcp(c) => std::pin::Pin::new(c).poll_flush(cx),
+            Encryption::TcpTls(t) => std::pin::Pin::new(t.as_mut()).poll_flush(cx),
+        }
+    }
+    fn poll_close(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<IoResult<()>> {
+        match self.get_mut() {
+            Encryption::Tcp(c) => std::pin::Pin::new(c).poll_close(cx),
+            Encryption::TcpTls(t) => std::pin::Pin::new(t.as_mut()).poll_close(cx),
+        }
+    }
+}
+
+/// A runtime capability for accepting incoming TLS connections.
+///
+/// This is the server-side counterpart of the existing client-only TLS
+/// traits: instead of wrapping an outgoing `TcpStream` in TLS, it wraps one
+/// we've just accepted from a listener.
+#[async_trait::async_trait]
+pub trait TlsListenerProvider {
+    /// The stream type produced once the TLS handshake completes.
+    type TlsStream: futures::io::AsyncRead + futures::io::AsyncWrite + Send + Unpin;
+
+    /// Perform a TLS server-side handshake on `stream`, authenticating
+    /// ourselves to the peer with `config`.
+    async fn accept_tls(
+        &self,
+        config: &TlsServerConfig,
+        stream: crate::impls::smol::net::TcpStream,
+    ) -> IoResult<Self::TlsStream>;
+}
+
 /// A [`Runtime`](crate::Runtime) powered by smol and native-tls.
 #[derive(Clone)]
 #[cfg(feature = "native-tls")]
@@ -46,6 +182,43 @@ crate::opaque::implement_opaque_runtime! {
     SmolNativeTlsRuntime { inner: NativeTlsInner }
 }
 
+#[cfg(feature = "native-tls")]
+#[async_trait::async_trait]
+impl TlsListenerProvider for NativeTlsProvider {
+    type TlsStream = async_native_tls::TlsStream<crate::impls::smol::net::TcpStream>;
+
+    async fn accept_tls(
+        &self,
+        config: &TlsServerConfig,
+        stream: crate::impls::smol::net::TcpStream,
+    ) -> IoResult<Self::TlsStream> {
+        let leaf_cert = config
+            .cert_chain
+            .first()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "empty cert chain"))?;
+        let identity = native_tls::Identity::from_pkcs8(leaf_cert, &config.private_key)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let mut builder = native_tls::TlsAcceptor::builder(identity);
+        if !config.alpn_protocols.is_empty() {
+            let protocols: Vec<&str> = config
+                .alpn_protocols
+                .iter()
+                .filter_map(|p| std::str::from_utf8(p).ok())
+                .collect();
+            builder.request_alpns(&protocols);
+        }
+        let acceptor = async_native_tls::TlsAcceptor::from(
+            builder
+                .build()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        );
+        acceptor
+            .accept(stream)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+    }
+}
+
 /// A [`Runtime`](crate::Runtime) powered by smol and rustls.
 #[derive(Clone)]
 #[cfg(feature = "rustls")]
@@ -69,6 +242,34 @@ crate::opaque::implement_opaque_runtime! {
     SmolRustlsRuntime { inner: RustlsInner }
 }
 
+#[cfg(feature = "rustls")]
+#[async_trait::async_trait]
+impl TlsListenerProvider for RustlsProvider {
+    type TlsStream = futures_rustls::server::TlsStream<crate::impls::smol::net::TcpStream>;
+
+    async fn accept_tls(
+        &self,
+        config: &TlsServerConfig,
+        stream: crate::impls::smol::net::TcpStream,
+    ) -> IoResult<Self::TlsStream> {
+        let cert_chain = config
+            .cert_chain
+            .iter()
+            .cloned()
+            .map(rustls::pki_types::CertificateDer::from)
+            .collect();
+        let private_key = rustls::pki_types::PrivateKeyDer::try_from(config.private_key.clone())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let mut server_config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, private_key)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        server_config.alpn_protocols = config.alpn_protocols.clone();
+        let acceptor = futures_rustls::TlsAcceptor::from(std::sync::Arc::new(server_config));
+        acceptor.accept(stream).await
+    }
+}
+
 #[cfg(feature = "native-tls")]
 impl SmolNativeTlsRuntime {
     /// Create a new `SmolNativeTlsRuntime` (owns its executor).
@@ -104,6 +305,372 @@ impl SmolNativeTlsRuntime {
         let runtime = Self::create().expect("Failed to create runtime");
         runtime.clone().block_on(func(runtime))
     }
+
+    /// Accept a TLS connection on `stream`, authenticating ourselves to the
+    /// peer with `config`.
+    pub async fn listen_tls(
+        &self,
+        config: &TlsServerConfig,
+        stream: crate::impls::smol::net::TcpStream,
+    ) -> IoResult<<NativeTlsProvider as TlsListenerProvider>::TlsStream> {
+        NativeTlsProvider::default().accept_tls(config, stream).await
+    }
+}
+
+/// Once the consumed prefix of a [`BufferedSocket`]'s read buffer grows past
+/// this many bytes, we compact the buffer to keep it from growing without
+/// bound.
+const BUFFERED_SOCKET_COMPACT_THRESHOLD: usize = 8 * 1024;
+
+/// The amount we grow a [`BufferedSocket`]'s read buffer by on each fill.
+const BUFFERED_SOCKET_READ_CHUNK: usize = 4 * 1024;
+
+/// An I/O wrapper that buffers reads and writes over any
+/// `AsyncRead + AsyncWrite`, to cut the syscall count for protocols (like the
+/// SOCKS handshake, or line-oriented directory parsing) that read many
+/// small, variable-length framed messages.
+///
+/// Works identically regardless of which runtime backs `S`.
+pub struct BufferedSocket<S> {
+    /// The wrapped stream.
+    inner: S,
+    /// Bytes read from `inner` that haven't been consumed by the caller yet.
+    ///
+    /// `read_buf[..read_pos]` has already been consumed and is logically
+    /// garbage; `read_buf[read_pos..]` is the data available to a caller.
+    read_buf: Vec<u8>,
+    /// The index in `read_buf` before which everything has been consumed.
+    read_pos: usize,
+    /// Bytes queued to be written to `inner`, but not yet flushed.
+    write_buf: Vec<u8>,
+}
+
+impl<S> BufferedSocket<S> {
+    /// Wrap `inner` in a new, empty `BufferedSocket`.
+    pub fn new(inner: S) -> Self {
+        BufferedSocket {
+            inner,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            write_buf: Vec::new(),
+        }
+    }
+
+    /// Return the bytes that have been read but not yet consumed, as a
+    /// contiguous slice.
+    ///
+    /// A parser like the SOCKS `Handshake` framework can drive itself off
+    /// this slice, and return `Truncated` when it needs more bytes than are
+    /// currently available.
+    pub fn available(&self) -> &[u8] {
+        &self.read_buf[self.read_pos..]
+    }
+
+    /// Peek at the next `n` bytes without consuming them, or `None` if fewer
+    /// than `n` bytes are currently buffered.
+    pub fn peek(&self, n: usize) -> Option<&[u8]> {
+        let available = self.available();
+        (available.len() >= n).then(|| &available[..n])
+    }
+
+    /// Mark the first `n` bytes of [`available`](Self::available) as
+    /// consumed, so they won't be returned again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is greater than `self.available().len()`.
+    pub fn consume(&mut self, n: usize) {
+        assert!(n <= self.available().len(), "consumed past end of buffer");
+        self.read_pos += n;
+        if self.read_pos >= BUFFERED_SOCKET_COMPACT_THRESHOLD {
+            self.read_buf.drain(..self.read_pos);
+            self.read_pos = 0;
+        }
+    }
+
+    /// Queue `data` to be written, without issuing a syscall.
+    ///
+    /// The write is coalesced with any other pending writes, and only
+    /// actually sent to the underlying stream by the next call to
+    /// [`flush`](Self::flush).
+    pub fn write_buffer(&mut self, data: &[u8]) {
+        self.write_buf.extend_from_slice(data);
+    }
+}
+
+impl<S: futures::io::AsyncRead + Unpin> BufferedSocket<S> {
+    /// Read more bytes from the underlying stream, growing the buffer as
+    /// needed, until at least `min_bytes` are available or the stream hits
+    /// EOF.
+    ///
+    /// Returns the number of bytes now available.
+    pub async fn fill(&mut self, min_bytes: usize) -> IoResult<usize> {
+        use futures::io::AsyncReadExt;
+        while self.available().len() < min_bytes {
+            let old_len = self.read_buf.len();
+            self.read_buf.resize(old_len + BUFFERED_SOCKET_READ_CHUNK, 0);
+            let n = self.inner.read(&mut self.read_buf[old_len..]).await?;
+            self.read_buf.truncate(old_len + n);
+            if n == 0 {
+                break;
+            }
+        }
+        Ok(self.available().len())
+    }
+
+    /// Report whether the underlying stream is ready to be read from right
+    /// now, without blocking.
+    pub fn poll_read_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<IoResult<()>> {
+        let mut probe = [0u8; 0];
+        match std::pin::Pin::new(&mut self.inner).poll_read(cx, &mut probe) {
+            std::task::Poll::Ready(Ok(_)) => std::task::Poll::Ready(Ok(())),
+            std::task::Poll::Ready(Err(e)) => std::task::Poll::Ready(Err(e)),
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+
+    /// Try to read more bytes into the buffer without awaiting.
+    ///
+    /// Returns `Poll::Pending` if the underlying stream isn't ready yet,
+    /// without losing any bytes that were already buffered.
+    pub fn try_read(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<IoResult<usize>> {
+        let old_len = self.read_buf.len();
+        self.read_buf.resize(old_len + BUFFERED_SOCKET_READ_CHUNK, 0);
+        let result = std::pin::Pin::new(&mut self.inner).poll_read(cx, &mut self.read_buf[old_len..]);
+        match result {
+            std::task::Poll::Ready(Ok(n)) => {
+                self.read_buf.truncate(old_len + n);
+                std::task::Poll::Ready(Ok(n))
+            }
+            std::task::Poll::Ready(Err(e)) => {
+                self.read_buf.truncate(old_len);
+                std::task::Poll::Ready(Err(e))
+            }
+            std::task::Poll::Pending => {
+                self.read_buf.truncate(old_len);
+                std::task::Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S: futures::io::AsyncWrite + Unpin> BufferedSocket<S> {
+    /// Flush any bytes queued by [`write_buffer`](Self::write_buffer) to the
+    /// underlying stream, then flush the stream itself.
+    pub async fn flush(&mut self) -> IoResult<()> {
+        use futures::io::AsyncWriteExt;
+        if !self.write_buf.is_empty() {
+            self.inner.write_all(&self.write_buf).await?;
+            self.write_buf.clear();
+        }
+        self.inner.flush().await
+    }
+}
+
+/// A thin QUIC transport built on quinn.
+///
+/// Its endpoints bind their own `std::net::UdpSocket`s directly;
+/// [`traits::UdpProvider`](crate::traits::UdpProvider) is not used.
+///
+/// This gives arti a foundation for QUIC-based pluggable transports and
+/// lower-latency link layers, without disturbing anyone who doesn't enable
+/// the `quic` feature.
+#[cfg(feature = "quic")]
+pub mod quic {
+    use super::IoResult;
+    use async_trait::async_trait;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    /// A runtime capability for establishing and accepting QUIC connections.
+    #[async_trait]
+    pub trait QuicProvider {
+        /// Open a QUIC connection to `addr`, presenting `server_name` via SNI
+        /// and authenticating the peer with `tls_client_config`.
+        async fn connect(
+            &self,
+            addr: &SocketAddr,
+            server_name: &str,
+            tls_client_config: Arc<rustls::ClientConfig>,
+        ) -> IoResult<QuicConnection>;
+
+        /// Bind a QUIC endpoint on `addr` that accepts incoming connections
+        /// authenticated with `tls_server_config`.
+        async fn listen(
+            &self,
+            addr: &SocketAddr,
+            tls_server_config: Arc<rustls::ServerConfig>,
+        ) -> IoResult<QuicEndpoint>;
+    }
+
+    /// A single multiplexed bidirectional stream within a [`QuicConnection`].
+    ///
+    /// Implements `AsyncRead`/`AsyncWrite`, like any other arti stream type.
+    pub struct QuicStream {
+        /// The outgoing half of the stream.
+        send: tokio_util::compat::Compat<quinn::SendStream>,
+        /// The incoming half of the stream.
+        recv: tokio_util::compat::Compat<quinn::RecvStream>,
+    }
+
+    impl futures::io::AsyncRead for QuicStream {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut [u8],
+        ) -> std::task::Poll<IoResult<usize>> {
+            std::pin::Pin::new(&mut self.recv).poll_read(cx, buf)
+        }
+    }
+
+    impl futures::io::AsyncWrite for QuicStream {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<IoResult<usize>> {
+            std::pin::Pin::new(&mut self.send).poll_write(cx, buf)
+        }
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<IoResult<()>> {
+            std::pin::Pin::new(&mut self.send).poll_flush(cx)
+        }
+        fn poll_close(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<IoResult<()>> {
+            std::pin::Pin::new(&mut self.send).poll_close(cx)
+        }
+    }
+
+    /// A single QUIC connection, multiplexing bidirectional streams and
+    /// unreliable datagrams over one UDP socket.
+    #[derive(Clone)]
+    pub struct QuicConnection {
+        /// The underlying quinn connection.
+        conn: quinn::Connection,
+    }
+
+    impl QuicConnection {
+        /// Open a new outgoing bidirectional stream.
+        pub async fn open_bi(&self) -> IoResult<QuicStream> {
+            let (send, recv) = self
+                .conn
+                .open_bi()
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            Ok(QuicStream {
+                send: tokio_util::compat::TokioAsyncWriteCompatExt::compat_write(send),
+                recv: tokio_util::compat::TokioAsyncReadCompatExt::compat(recv),
+            })
+        }
+
+        /// Wait for the peer to open a bidirectional stream.
+        pub async fn accept_bi(&self) -> IoResult<QuicStream> {
+            let (send, recv) = self
+                .conn
+                .accept_bi()
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            Ok(QuicStream {
+                send: tokio_util::compat::TokioAsyncWriteCompatExt::compat_write(send),
+                recv: tokio_util::compat::TokioAsyncReadCompatExt::compat(recv),
+            })
+        }
+
+        /// Send an unreliable datagram.
+        pub fn send_datagram(&self, data: bytes::Bytes) -> IoResult<()> {
+            self.conn
+                .send_datagram(data)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        }
+
+        /// Receive an unreliable datagram.
+        pub async fn recv_datagram(&self) -> IoResult<bytes::Bytes> {
+            self.conn
+                .read_datagram()
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        }
+    }
+
+    /// A bound QUIC listener, accepting incoming connections.
+    pub struct QuicEndpoint {
+        /// The underlying quinn endpoint.
+        endpoint: quinn::Endpoint,
+    }
+
+    impl QuicEndpoint {
+        /// Await the next incoming connection.
+        pub async fn accept(&self) -> IoResult<QuicConnection> {
+            let incoming = self.endpoint.accept().await.ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "QUIC endpoint closed")
+            })?;
+            let conn = incoming
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            Ok(QuicConnection { conn })
+        }
+    }
+
+    #[async_trait]
+    impl QuicProvider for super::SmolRuntimeHandle {
+        async fn connect(
+            &self,
+            addr: &SocketAddr,
+            server_name: &str,
+            tls_client_config: Arc<rustls::ClientConfig>,
+        ) -> IoResult<QuicConnection> {
+            let client_config = quinn::ClientConfig::new(Arc::new(
+                quinn::crypto::rustls::QuicClientConfig::try_from((*tls_client_config).clone())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            ));
+            // TODO: reuse a socket obtained via `UdpProvider::bind` instead of
+            // opening a fresh one, once `UdpSocket` exposes a way to hand its
+            // file descriptor to quinn.
+            let local_addr: SocketAddr = if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" }
+                .parse()
+                .expect("valid wildcard address");
+            let socket = std::net::UdpSocket::bind(local_addr)?;
+            let mut endpoint = quinn::Endpoint::new(
+                quinn::EndpointConfig::default(),
+                None,
+                socket,
+                Arc::new(quinn::TokioRuntime),
+            )?;
+            endpoint.set_default_client_config(client_config);
+            let conn = endpoint
+                .connect(*addr, server_name)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            Ok(QuicConnection { conn })
+        }
+
+        async fn listen(
+            &self,
+            addr: &SocketAddr,
+            tls_server_config: Arc<rustls::ServerConfig>,
+        ) -> IoResult<QuicEndpoint> {
+            let server_config = quinn::ServerConfig::with_crypto(Arc::new(
+                quinn::crypto::rustls::QuicServerConfig::try_from((*tls_server_config).clone())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            ));
+            let socket = std::net::UdpSocket::bind(addr)?;
+            let endpoint = quinn::Endpoint::new(
+                quinn::EndpointConfig::default(),
+                Some(server_config),
+                socket,
+                Arc::new(quinn::TokioRuntime),
+            )?;
+            Ok(QuicEndpoint { endpoint })
+        }
+    }
 }
 
 #[cfg(feature = "rustls")]
@@ -139,4 +706,14 @@ impl SmolRustlsRuntime {
         let runtime = Self::create().expect("Failed to create runtime");
         runtime.clone().block_on(func(runtime))
     }
+
+    /// Accept a TLS connection on `stream`, authenticating ourselves to the
+    /// peer with `config`.
+    pub async fn listen_tls(
+        &self,
+        config: &TlsServerConfig,
+        stream: crate::impls::smol::net::TcpStream,
+    ) -> IoResult<<RustlsProvider as TlsListenerProvider>::TlsStream> {
+        RustlsProvider::default().accept_tls(config, stream).await
+    }
 }